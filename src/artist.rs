@@ -0,0 +1,152 @@
+use crate::{
+    common::{get_json, get_json_list, pipeline_args, user_agent, valid_parse, Error, Result},
+    dtext::{self, Format},
+    normal, wiki,
+};
+use clap::{App, Arg, ArgMatches, SubCommand};
+use rs621::client::Client;
+use serde::Deserialize;
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("artist")
+        .about("Show information about an artist")
+        .arg(
+            Arg::with_name("name")
+                .index(1)
+                .required(true)
+                .help("Name of the artist (their tag)"),
+        )
+        .arg(
+            Arg::with_name("posts")
+                .long("posts")
+                .help("Search the posts of the artist instead"),
+        )
+        .arg(
+            Arg::with_name("limit")
+                .short("l")
+                .long("limit")
+                .takes_value(true)
+                .default_value("1")
+                .validator(|v| valid_parse::<u64>(&v, "Must be a positive integer."))
+                .help("Maximum search result count with --posts"),
+        )
+        .args(&pipeline_args())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ArtistUrl {
+    pub url: String,
+    pub is_active: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Artist {
+    pub id: u64,
+    pub name: String,
+    #[serde(default)]
+    pub other_names: Vec<String>,
+    #[serde(default)]
+    pub group_name: String,
+    pub linked_user_id: Option<u64>,
+    pub notes: Option<String>,
+    #[serde(default)]
+    pub urls: Vec<ArtistUrl>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AvoidPosting {
+    pub is_active: bool,
+    #[serde(default)]
+    pub details: String,
+}
+
+/// Gets the artist with the given name, along with their URLs.
+pub async fn get_artist(url: &str, name: &str) -> Result<Option<Artist>> {
+    let artists: Vec<Artist> =
+        get_json_list(url, "/artists.json", &[("search[name]", name)]).await?;
+
+    // URLs are only included when requesting the artist itself
+    match artists.into_iter().next() {
+        Some(artist) => Ok(Some(
+            get_json(url, &format!("/artists/{}.json", artist.id), &[]).await?,
+        )),
+        None => Ok(None),
+    }
+}
+
+/// Gets the active avoid-posting entry of an artist, if they have one.
+pub async fn avoid_posting(url: &str, name: &str) -> Result<Option<AvoidPosting>> {
+    let entries: Vec<AvoidPosting> = get_json_list(
+        url,
+        "/avoid_postings.json",
+        &[("search[artist_name]", name)],
+    )
+    .await?;
+
+    Ok(entries.into_iter().find(|entry| entry.is_active))
+}
+
+// get621 artist ...
+pub async fn run(url: &str, matches: &ArgMatches<'_>) -> Result<()> {
+    // artist names are written like tags
+    let name = matches.value_of("name").unwrap().to_lowercase();
+
+    if matches.is_present("posts") {
+        let client = Client::new(url, user_agent())?;
+
+        return normal::search(url, &client, vec![name], matches).await;
+    }
+
+    let artist = get_artist(url, &name)
+        .await?
+        .ok_or(Error::ArtistNotFound(name))?;
+    let format = Format::terminal();
+
+    println!("{}", artist.name);
+
+    if !artist.other_names.is_empty() {
+        println!("Other names: {}", artist.other_names.join(", "));
+    }
+
+    if !artist.group_name.is_empty() {
+        println!("Group: {}", artist.group_name);
+    }
+
+    if let Some(user_id) = artist.linked_user_id {
+        println!("Linked user: #{}", user_id);
+    }
+
+    if let Some(entry) = avoid_posting(url, &artist.name).await? {
+        if entry.details.is_empty() {
+            println!("Avoid posting: yes");
+        } else {
+            println!(
+                "Avoid posting: {}",
+                dtext::render(&entry.details, format).trim_end()
+            );
+        }
+    }
+
+    if let Some(page) = wiki::get_page(url, &artist.name).await? {
+        println!("Wiki: {}", page.title);
+    }
+
+    if !artist.urls.is_empty() {
+        println!("URLs:");
+
+        for artist_url in artist.urls.iter() {
+            if artist_url.is_active {
+                println!("  {}", artist_url.url);
+            } else {
+                println!("  {} (inactive)", artist_url.url);
+            }
+        }
+    }
+
+    if let Some(notes) = artist.notes.as_deref().filter(|notes| !notes.is_empty()) {
+        println!();
+        print!("{}", dtext::render(notes, format));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,84 @@
+use crate::{
+    common::{get_json_pages, valid_parse, Result},
+    dtext::{self, Format},
+};
+use clap::{App, Arg, ArgMatches, SubCommand};
+use serde::{Deserialize, Serialize};
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("comments")
+        .about("Show the comments of a post")
+        .arg(
+            Arg::with_name("id")
+                .index(1)
+                .required(true)
+                .validator(|v| valid_parse::<u64>(&v, "Must be a positive integer."))
+                .help("The ID of the post"),
+        )
+        .arg(
+            Arg::with_name("output_mode")
+                .short("o")
+                .long("output")
+                .takes_value(true)
+                .default_value("text")
+                .possible_values(&["text", "json"])
+                .help("Set output mode; one of: text, json"),
+        )
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Comment {
+    pub id: u64,
+    pub creator_name: String,
+    pub created_at: String,
+    pub score: i64,
+    pub body: String,
+}
+
+/// Gets the comments of a post, oldest first.
+pub async fn get_comments(url: &str, post_id: u64) -> Result<Vec<Comment>> {
+    let mut comments: Vec<Comment> = get_json_pages(
+        url,
+        "/comments.json",
+        &[
+            ("group_by", "comment"),
+            ("search[post_id]", &post_id.to_string()),
+        ],
+    )
+    .await?;
+
+    comments.sort_by_key(|comment| comment.id);
+
+    Ok(comments)
+}
+
+// get621 comments ...
+pub async fn run(url: &str, matches: &ArgMatches<'_>) -> Result<()> {
+    let post_id = matches.value_of("id").unwrap().parse().unwrap();
+    let comments = get_comments(url, post_id).await?;
+
+    if matches.value_of("output_mode") == Some("json") {
+        println!("{}", serde_json::to_string_pretty(&comments)?);
+        return Ok(());
+    }
+
+    let format = Format::terminal();
+
+    for (i, comment) in comments.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+
+        println!(
+            "{} on {} (score: {})",
+            comment.creator_name, comment.created_at, comment.score
+        );
+        print!("{}", dtext::render(&comment.body, format));
+    }
+
+    if comments.is_empty() {
+        println!("No comments on #{}.", post_id);
+    }
+
+    Ok(())
+}
@@ -1,20 +1,49 @@
-use clap::ArgMatches;
-use futures::{pin_mut, stream::StreamExt, Stream};
-use glob;
+use crate::{
+    comments::{self, Comment},
+    dtext::{self, Format},
+    filter::{filter_args, PostFilter},
+    notes::{self, Note, NotesMode},
+};
+use clap::{crate_version, Arg, ArgMatches};
+use futures::{
+    future, pin_mut,
+    stream::{self, LocalBoxStream, StreamExt},
+    Stream,
+};
 use lazy_static::lazy_static;
-use reqwest;
+use reqwest::{self, multipart::Part};
 use rs621::{
     client::Client as Rs621Client,
-    error::Result as Rs621Result,
     post::{Post, PostFileExtension, PostRating},
 };
-use std::{fmt, fs::File, io, path::PathBuf, str::FromStr};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    fs::File,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::atomic::{AtomicU8, Ordering},
+};
 
 lazy_static! {
     pub static ref CLIENT: reqwest::Client = reqwest::Client::builder().build().unwrap();
 }
 
+/// Machine-wide configuration file, meant to be only writable by administrators.
+#[cfg(not(windows))]
+pub const CONFIG_PATH: &str = "/etc/get621.toml";
+
+/// Machine-wide configuration file, meant to be only writable by administrators.
+#[cfg(windows)]
+pub const CONFIG_PATH: &str = r"C:\ProgramData\get621\get621.toml";
+
+// most explicit rating that can be output or saved, see `init_max_rating`
+static MAX_RATING: AtomicU8 = AtomicU8::new(2);
+
 #[derive(Debug, thiserror::Error)]
+#[allow(clippy::enum_variant_names)]
 pub enum Error {
     #[error("API error: {0}")]
     Rs621Error(#[from] rs621::error::Error),
@@ -36,8 +65,34 @@ pub enum Error {
     AuthTokenNotFound,
     #[error("The IQDB query response couldn't be interpreted: {0}")]
     IqdbResponseParseError(String),
+    #[error("SauceNAO error: {0}")]
+    SauceNaoError(String),
+    #[error("Image error: {0}")]
+    ImageError(#[from] image::ImageError),
+    #[error("Artist not found: {0}")]
+    ArtistNotFound(String),
+    #[error("User not found: {0}")]
+    UserNotFound(String),
+    #[error("Wiki page not found: {0}")]
+    WikiPageNotFound(String),
+    #[error("Invalid search query: {0}")]
+    QueryError(String),
+    #[error("Invalid post reference: {0}")]
+    InvalidPostReference(String),
+    #[error("Invalid configuration file {0}: {1}")]
+    ConfigError(PathBuf, String),
+    #[error("The post is rated {0}, which is above the maximum rating")]
+    RatingNotAllowed(&'static str),
     #[error("A post is missing a file URL")]
     MissingFileUrl,
+    #[error("No usable font found, use --font to give one")]
+    FontNotFound,
+    #[error("This command requires authentication, use --login and --api-key")]
+    AuthRequired,
+    #[error("Invalid upload: {0}")]
+    InvalidUpload(String),
+    #[error("Upload failed: {0}")]
+    UploadFailed(String),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -46,6 +101,8 @@ pub enum OutputMode {
     Id,
     Raw,
     Verbose,
+    Tree,
+    Dot,
 }
 
 impl From<&str> for OutputMode {
@@ -54,6 +111,8 @@ impl From<&str> for OutputMode {
             "id" => OutputMode::Id,
             "raw" => OutputMode::Raw,
             "verbose" => OutputMode::Verbose,
+            "tree" => OutputMode::Tree,
+            "dot" => OutputMode::Dot,
             _ => panic!("Invalid output mode: {}", s),
         }
     }
@@ -68,7 +127,7 @@ pub fn valid_parse<T: FromStr>(v: &str, emsg: &str) -> std::result::Result<(), S
 }
 
 pub fn output_mode_check(v: String) -> std::result::Result<(), String> {
-    if v == "id" || v == "raw" || v == "verbose" {
+    if ["id", "raw", "verbose", "tree", "dot"].contains(&v.as_str()) {
         Ok(())
     } else {
         Err(String::from("Invalid output mode."))
@@ -79,21 +138,42 @@ pub fn output_mode_check(v: String) -> std::result::Result<(), String> {
 pub fn expand_paths<S: AsRef<str>>(patterns: &[S]) -> Result<Vec<PathBuf>> {
     let mut results = Vec::new();
 
-    for p in patterns.into_iter() {
+    for p in patterns.iter() {
         let p = p.as_ref();
 
         for entry in glob::glob(p)?.filter_map(std::result::Result::ok) {
-            results.push(entry.canonicalize()?);
+            if entry.is_dir() {
+                let pattern = Path::new(&glob::Pattern::escape(&entry.to_string_lossy()))
+                    .join("**")
+                    .join("*");
+
+                for file in glob::glob(&pattern.to_string_lossy())?
+                    .filter_map(std::result::Result::ok)
+                    .filter(|file| file.is_file())
+                {
+                    results.push(file.canonicalize()?);
+                }
+            } else {
+                results.push(entry.canonicalize()?);
+            }
         }
     }
 
     Ok(results)
 }
 
+// maximum number of results per page of a list endpoint
+const API_LIST_LIMIT: usize = 320;
+
+// maximum number of IDs in an "id:" search
+const MAX_IDS_PER_REQUEST: usize = 100;
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum PostMapMode {
     Parents,
     Children,
+    Siblings,
+    Family,
     None,
 }
 
@@ -103,61 +183,518 @@ impl From<&ArgMatches<'_>> for PostMapMode {
             PostMapMode::Parents
         } else if matches.is_present("children") {
             PostMapMode::Children
+        } else if matches.is_present("siblings") {
+            PostMapMode::Siblings
+        } else if matches.is_present("family") {
+            PostMapMode::Family
         } else {
             PostMapMode::None
         }
     }
 }
 
-pub async fn post_map(
+/// How `post_map` maps posts.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PostMap {
+    pub mode: PostMapMode,
+    /// Whether to keep each original post in front of its relatives.
+    pub with_original: bool,
+}
+
+impl From<&ArgMatches<'_>> for PostMap {
+    fn from(matches: &ArgMatches) -> Self {
+        PostMap {
+            mode: matches.into(),
+            with_original: matches.is_present("with_original"),
+        }
+    }
+}
+
+// arguments selecting the PostMap
+pub fn post_map_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("children")
+            .short("c")
+            .long("children")
+            .conflicts_with_all(&["parents", "siblings", "family"])
+            .help("Take the children of search results"),
+        Arg::with_name("parents")
+            .short("p")
+            .long("parents")
+            .conflicts_with_all(&["children", "siblings", "family"])
+            .help("Take the parent post of each search result, if any"),
+        Arg::with_name("siblings")
+            .long("siblings")
+            .conflicts_with_all(&["children", "parents", "family"])
+            .help("Take the other children of the parent of each search result"),
+        Arg::with_name("family")
+            .short("f")
+            .long("family")
+            .conflicts_with_all(&["children", "parents", "siblings"])
+            .help("Take every post related to search results, recursively"),
+        Arg::with_name("with_original")
+            .long("with-original")
+            .help("Keep search results along with the posts they are mapped to"),
+    ]
+}
+
+/// Arguments of the commands saving posts, choosing what is saved along with them.
+pub fn save_extras_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("with_comments")
+            .long("with-comments")
+            .requires("save")
+            .help("Write the comments of saved posts to <file>.json"),
+        Arg::with_name("with_notes")
+            .long("with-notes")
+            .takes_value(true)
+            .possible_values(&["sidecar", "burn"])
+            .requires("save")
+            .help(
+                "Write the notes of saved posts to <file>.json or draw them on a copy of the image",
+            ),
+        Arg::with_name("font")
+            .long("font")
+            .takes_value(true)
+            .requires("with_notes")
+            .help("TrueType font used to draw notes [default: DejaVu Sans or Arial]"),
+    ]
+}
+
+/// What is saved along with posts, according to the arguments given by `save_extras_args`.
+#[derive(Debug, Clone, Default)]
+pub struct SaveExtras {
+    pub comments: bool,
+    pub notes: Option<NotesMode>,
+    pub font: Option<PathBuf>,
+}
+
+impl From<&ArgMatches<'_>> for SaveExtras {
+    fn from(matches: &ArgMatches<'_>) -> Self {
+        SaveExtras {
+            comments: matches.is_present("with_comments"),
+            notes: matches.value_of("with_notes").map(NotesMode::from),
+            font: matches.value_of("font").map(PathBuf::from),
+        }
+    }
+}
+
+/// Arguments of the commands using `process_posts`.
+pub fn pipeline_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    let mut args = vec![
+        Arg::with_name("save")
+            .short("s")
+            .long("save")
+            .help("Download every result to ./<post_id>.<ext>"),
+        Arg::with_name("output_mode")
+            .short("o")
+            .long("output")
+            .takes_value(true)
+            .default_value("verbose")
+            .validator(output_mode_check)
+            .help("Set output mode; one of: id, raw, verbose, tree, dot"),
+    ];
+
+    args.extend(save_extras_args());
+    args.extend(post_map_args());
+    args.extend(filter_args());
+    args
+}
+
+/// Maps, filters, saves and outputs posts according to the arguments given by `pipeline_args`.
+pub async fn process_posts<E>(
+    url: &str,
     client: &Rs621Client,
-    mode: PostMapMode,
-    mut post_stream: impl Stream<Item = Rs621Result<Post>> + Unpin,
-) -> Result<Vec<Post>> {
-    Ok(match mode {
-        PostMapMode::None => post_stream
-            .map(|r| r.map_err(|e| Error::from(e)))
-            .collect::<Vec<_>>()
-            .await
-            .into_iter()
-            .collect::<Result<Vec<_>>>()?,
-        PostMapMode::Parents => post_stream
-            .filter_map(|p| async move {
-                match p {
-                    Ok(p) => {
-                        if let Some(parent) = p.relationships.parent_id {
-                            client.get_posts(&[parent]).next().await
-                        } else {
-                            None
+    posts: impl Stream<Item = std::result::Result<Post, E>> + Unpin,
+    matches: &ArgMatches<'_>,
+) -> Result<()>
+where
+    Error: From<E>,
+{
+    let flag_save = matches.is_present("save");
+    let extras = SaveExtras::from(matches);
+    let extras = &extras;
+    let filter = PostFilter::from(matches);
+
+    let posts = report_errors(post_map(client, matches.into(), posts))
+        .filter(move |post| future::ready(rating_allowed(&post.rating) && filter.matches(post)));
+    let posts = posts.then(|post| async move {
+        if flag_save {
+            if let Err(e) = save_post_with_extras(url, &post, None, extras).await {
+                eprintln!("Error when saving #{}: {}", post.id, e);
+            }
+        }
+
+        post
+    });
+    pin_mut!(posts);
+
+    // Do whatever the user asked us to do
+    output_posts(posts, matches.value_of("output_mode").unwrap().into()).await
+}
+
+// gets a batch of at most `MAX_IDS_PER_REQUEST` posts, in the same order
+async fn fetch_batch(client: &Rs621Client, ids: &[u64]) -> Result<Vec<Post>> {
+    // a search returns deleted posts with "status:any", and more than the 75 posts that a lookup
+    // by ID returns by default
+    let id_tag = format!(
+        "id:{}",
+        ids.iter().map(u64::to_string).collect::<Vec<_>>().join(",")
+    );
+    let mut posts = client.post_search(&[&id_tag[..], "status:any"][..]);
+    let mut by_id = HashMap::with_capacity(ids.len());
+
+    while let Some(post) = posts.next().await {
+        let post = post?;
+        by_id.insert(post.id, post);
+    }
+
+    Ok(ids.iter().filter_map(|id| by_id.remove(id)).collect())
+}
+
+/// Gets the posts with the given IDs, in the same order, in as few requests as possible.
+///
+/// Posts that don't exist are skipped.
+pub async fn fetch_posts(client: &Rs621Client, ids: &[u64]) -> Result<Vec<Post>> {
+    let mut posts = Vec::with_capacity(ids.len());
+
+    for batch in ids.chunks(MAX_IDS_PER_REQUEST) {
+        posts.extend(fetch_batch(client, batch).await?);
+    }
+
+    Ok(posts)
+}
+
+/// Streams the posts with the given IDs, in the same order, fetching them in batches.
+///
+/// The posts of a batch are yielded as soon as it is fetched.
+pub fn stream_posts<'a>(
+    client: &'a Rs621Client,
+    ids: &'a [u64],
+) -> LocalBoxStream<'a, Result<Post>> {
+    stream::iter(ids.chunks(MAX_IDS_PER_REQUEST))
+        .then(move |batch| fetch_batch(client, batch))
+        .flat_map(|posts| {
+            stream::iter(match posts {
+                Ok(posts) => posts.into_iter().map(Ok).collect(),
+                Err(e) => vec![Err(e)],
+            })
+        })
+        .boxed_local()
+}
+
+// IDs of the parent and children of a post
+fn relatives(post: &Post) -> impl Iterator<Item = u64> + '_ {
+    post.relationships
+        .parent_id
+        .into_iter()
+        .chain(post.relationships.children.iter().copied())
+}
+
+/// Maps a stream of posts according to `map`.
+///
+/// Posts are yielded as soon as they are available. Errors are yielded in place of the posts
+/// they concern, without interrupting the stream.
+pub fn post_map<'a, E: 'a>(
+    client: &'a Rs621Client,
+    map: PostMap,
+    post_stream: impl Stream<Item = std::result::Result<Post, E>> + Unpin + 'a,
+) -> LocalBoxStream<'a, Result<Post>>
+where
+    Error: From<E>,
+{
+    match map.mode {
+        PostMapMode::None => post_stream.map(|r| r.map_err(Error::from)).boxed_local(),
+        PostMapMode::Parents | PostMapMode::Children | PostMapMode::Siblings => stream::unfold(
+            // look the relatives up in batches, as soon as enough results are available
+            (
+                post_stream.ready_chunks(MAX_IDS_PER_REQUEST),
+                HashSet::new(),
+            ),
+            move |(mut chunks, mut seen)| async move {
+                let chunk = chunks.next().await?;
+                let mut results = Vec::new();
+                let mut posts = Vec::with_capacity(chunk.len());
+
+                for post in chunk {
+                    match post {
+                        Ok(post) => posts.push(post),
+                        Err(e) => results.push(Err(Error::from(e))),
+                    }
+                }
+
+                // siblings are the children of the parent
+                let parents: HashMap<u64, Post> = if map.mode == PostMapMode::Siblings {
+                    let mut ids = posts
+                        .iter()
+                        .filter_map(|p| p.relationships.parent_id)
+                        .collect::<Vec<_>>();
+                    ids.sort_unstable();
+                    ids.dedup();
+
+                    match fetch_posts(client, &ids).await {
+                        Ok(parents) => parents.into_iter().map(|p| (p.id, p)).collect(),
+                        Err(e) => {
+                            results.push(Err(e));
+                            HashMap::new()
+                        }
+                    }
+                } else {
+                    HashMap::new()
+                };
+
+                let relatives = posts
+                    .iter()
+                    .map(|post| match map.mode {
+                        PostMapMode::Parents => post.relationships.parent_id.into_iter().collect(),
+                        PostMapMode::Children => post.relationships.children.clone(),
+                        _ => post
+                            .relationships
+                            .parent_id
+                            .and_then(|parent| parents.get(&parent))
+                            .map_or_else(Vec::new, |parent| {
+                                parent
+                                    .relationships
+                                    .children
+                                    .iter()
+                                    .copied()
+                                    .filter(|&id| id != post.id)
+                                    .collect()
+                            }),
+                    })
+                    .collect::<Vec<Vec<u64>>>();
+
+                // siblings often share a parent and posts can appear more than once in a pool
+                let mut ids = relatives.iter().flatten().copied().collect::<Vec<_>>();
+                ids.sort_unstable();
+                ids.dedup();
+
+                let mut fetched: HashMap<u64, Post> = match fetch_posts(client, &ids).await {
+                    Ok(fetched) => fetched.into_iter().map(|p| (p.id, p)).collect(),
+                    Err(e) => {
+                        results.push(Err(e));
+                        HashMap::new()
+                    }
+                };
+
+                for (post, relatives) in posts.into_iter().zip(relatives) {
+                    if map.with_original && seen.insert(post.id) {
+                        results.push(Ok(post));
+                    }
+
+                    for id in relatives {
+                        if seen.insert(id) {
+                            if let Some(relative) = fetched.remove(&id) {
+                                results.push(Ok(relative));
+                            }
                         }
                     }
-                    Err(e) => Some(Err(e)),
                 }
-            })
-            .map(|r| r.map_err(|e| Error::from(e)))
-            .collect::<Vec<_>>()
-            .await
-            .into_iter()
-            .collect::<Result<Vec<_>>>()?,
-        PostMapMode::Children => {
-            // Vec holding all the children of all the posts
-            let mut all_children = Vec::new();
-
-            // collect the children of every post in a Vec
-            while let Some(post) = post_stream.next().await {
-                let post = post?;
-
-                all_children.reserve(post.relationships.children.len());
-
-                for child in post.relationships.children.iter() {
-                    if let Some(child_post) = client.get_posts(&[*child]).next().await {
-                        all_children.push(child_post?);
+
+                Some((results, (chunks, seen)))
+            },
+        )
+        .flat_map(stream::iter)
+        .boxed_local(),
+        PostMapMode::Family => stream::unfold(
+            (Some(post_stream), HashSet::new(), Vec::new()),
+            move |(mut input, mut seen, mut pending)| async move {
+                // the results themselves come first
+                if let Some(posts) = input.as_mut() {
+                    match posts.next().await {
+                        Some(Ok(post)) => {
+                            let results = if seen.insert(post.id) {
+                                pending.extend(relatives(&post));
+                                vec![Ok(post)]
+                            } else {
+                                Vec::new()
+                            };
+
+                            return Some((results, (input, seen, pending)));
+                        }
+                        Some(Err(e)) => {
+                            return Some((vec![Err(Error::from(e))], (input, seen, pending)))
+                        }
+                        None => input = None,
                     }
                 }
+
+                // then the relationship graph is walked one level at a time, never fetching a
+                // post twice
+                let ids = pending
+                    .drain(..)
+                    .filter(|id| seen.insert(*id))
+                    .collect::<Vec<_>>();
+
+                if ids.is_empty() {
+                    return None;
+                }
+
+                let results = match fetch_posts(client, &ids).await {
+                    Ok(posts) => {
+                        for post in posts.iter() {
+                            pending.extend(relatives(post));
+                        }
+
+                        posts.into_iter().map(Ok).collect()
+                    }
+                    Err(e) => vec![Err(e)],
+                };
+
+                Some((results, (input, seen, pending)))
+            },
+        )
+        .flat_map(stream::iter)
+        .boxed_local(),
+    }
+}
+
+/// Prints the errors of a stream of results to stderr, only keeping the successful values.
+pub fn report_errors<'a, T: 'a>(
+    results: impl Stream<Item = Result<T>> + 'a,
+) -> impl Stream<Item = T> + 'a {
+    results.filter_map(|res| async move {
+        match res {
+            Ok(value) => Some(value),
+            Err(e) => {
+                eprintln!("{}", e);
+                None
             }
+        }
+    })
+}
 
-            all_children
+/// User agent sent with every request.
+pub fn user_agent() -> String {
+    format!("get621/{} (by nasso on e621)", crate_version!())
+}
+
+/// The user name and API key given with `--login` and `--api-key`.
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    pub login: String,
+    pub api_key: String,
+}
+
+impl Credentials {
+    /// Reads the credentials from the arguments, failing if they weren't given.
+    pub fn from_matches(matches: &ArgMatches<'_>) -> Result<Self> {
+        match (matches.value_of("login"), matches.value_of("api_key")) {
+            (Some(login), Some(api_key)) => Ok(Credentials {
+                login: login.into(),
+                api_key: api_key.into(),
+            }),
+            _ => Err(Error::AuthRequired),
         }
+    }
+
+    /// Adds the credentials and the user agent to a request.
+    pub fn authenticate(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        request
+            .basic_auth(&self.login, Some(&self.api_key))
+            .header("User-Agent", user_agent())
+    }
+}
+
+/// Sends a request authenticated with the given credentials.
+pub async fn send_authenticated(
+    request: reqwest::RequestBuilder,
+    credentials: &Credentials,
+) -> Result<reqwest::Response> {
+    let res = credentials.authenticate(request).send().await?;
+
+    if res.status().is_success() {
+        Ok(res)
+    } else {
+        Err(Error::Http(res.status().as_u16()))
+    }
+}
+
+/// Sends a GET request to an API endpoint (e.g. "/tags.json") and parses the JSON response.
+pub async fn get_json<T: DeserializeOwned>(
+    url: &str,
+    endpoint: &str,
+    query: &[(&str, &str)],
+) -> Result<T> {
+    let res = CLIENT
+        .get(format!("{}{}", url, endpoint))
+        .query(query)
+        .header("User-Agent", user_agent())
+        .send()
+        .await?;
+
+    if res.status().is_success() {
+        Ok(res.json().await?)
+    } else {
+        Err(Error::Http(res.status().as_u16()))
+    }
+}
+
+/// Same as `get_json` for endpoints returning a list.
+///
+/// The API returns an object such as `{"tags":[]}` instead of an empty list.
+pub async fn get_json_list<T: DeserializeOwned>(
+    url: &str,
+    endpoint: &str,
+    query: &[(&str, &str)],
+) -> Result<Vec<T>> {
+    let json: serde_json::Value = get_json(url, endpoint, query).await?;
+
+    match json {
+        serde_json::Value::Object(ref fields)
+            if fields
+                .values()
+                .all(|v| matches!(v.as_array(), Some(values) if values.is_empty())) =>
+        {
+            Ok(Vec::new())
+        }
+        json => Ok(serde_json::from_value(json)?),
+    }
+}
+
+/// Same as `get_json_list`, requesting pages of `API_LIST_LIMIT` results until one is short.
+pub async fn get_json_pages<T: DeserializeOwned>(
+    url: &str,
+    endpoint: &str,
+    query: &[(&str, &str)],
+) -> Result<Vec<T>> {
+    let limit = API_LIST_LIMIT.to_string();
+    let mut results = Vec::new();
+
+    for page in 1.. {
+        let page = page.to_string();
+        let mut page_query = query.to_vec();
+        page_query.extend_from_slice(&[("limit", &limit[..]), ("page", &page[..])]);
+
+        let values: Vec<T> = get_json_list(url, endpoint, &page_query).await?;
+        let done = values.len() < API_LIST_LIMIT;
+        results.extend(values);
+
+        if done {
+            break;
+        }
+    }
+
+    Ok(results)
+}
+
+/// Reads a file into a multipart form field, named after the file.
+pub fn file_part(path: &Path) -> Result<Part> {
+    let file_name = path
+        .file_name()
+        .map(|filename| filename.to_string_lossy().into_owned());
+    let mime = mime_guess::from_path(path).first_or_octet_stream();
+
+    let mut file = File::open(path)?;
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes)?;
+
+    let field = Part::bytes(bytes).mime_str(mime.essence_str())?;
+
+    Ok(if let Some(file_name) = file_name {
+        field.file_name(file_name)
+    } else {
+        field
     })
 }
 
@@ -185,6 +722,183 @@ where
     }
 }
 
+/// Iterates over the tags of every category of a post.
+pub fn all_tags(post: &Post) -> impl Iterator<Item = &String> {
+    let tags = &post.tags;
+
+    vec![
+        &tags.artist,
+        &tags.lore,
+        &tags.character,
+        &tags.species,
+        &tags.general,
+        &tags.meta,
+        &tags.invalid,
+    ]
+    .into_iter()
+    .flatten()
+}
+
+fn rating_level(rating: &PostRating) -> u8 {
+    match rating {
+        PostRating::Safe => 0,
+        PostRating::Questionable => 1,
+        PostRating::Explicit => 2,
+    }
+}
+
+/// Parses a rating name or its first letter, as found in the API.
+pub fn parse_rating_level(s: &str) -> Option<u8> {
+    match s.trim().to_lowercase().as_str() {
+        "s" | "safe" => Some(0),
+        "q" | "questionable" => Some(1),
+        "e" | "explicit" => Some(2),
+        _ => None,
+    }
+}
+
+/// Machine-wide settings that can't be overridden from the command line.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    /// Most explicit rating that can be output or saved, `--max-rating` can only lower it.
+    pub max_rating: Option<String>,
+}
+
+impl Config {
+    /// Reads a configuration file, a missing file being an empty configuration.
+    pub fn load(path: &Path) -> Result<Self> {
+        let config = match std::fs::read_to_string(path) {
+            Ok(config) => config,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Config::default()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let config: Config =
+            toml::from_str(&config).map_err(|e| Error::ConfigError(path.into(), e.to_string()))?;
+
+        if let Some(ref rating) = config.max_rating {
+            if parse_rating_level(rating).is_none() {
+                return Err(Error::ConfigError(
+                    path.into(),
+                    format!("invalid max-rating: {}", rating),
+                ));
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// The maximum rating level, given the one requested on the command line.
+    pub fn max_rating_level(&self, requested: Option<&str>) -> u8 {
+        let requested = requested.and_then(parse_rating_level).unwrap_or(2);
+        let locked = self
+            .max_rating
+            .as_deref()
+            .and_then(parse_rating_level)
+            .unwrap_or(2);
+
+        requested.min(locked)
+    }
+}
+
+/// Sets the maximum rating from the `--max-rating` argument and the configuration file.
+///
+/// Must be called before any post is output or saved.
+pub fn init_max_rating(matches: &ArgMatches<'_>) -> Result<()> {
+    let config = Config::load(Path::new(CONFIG_PATH))?;
+
+    MAX_RATING.store(
+        config.max_rating_level(matches.value_of("max_rating")),
+        Ordering::SeqCst,
+    );
+
+    Ok(())
+}
+
+/// Checks whether posts with the given rating can be output or saved.
+pub fn rating_allowed(rating: &PostRating) -> bool {
+    rating_level(rating) <= MAX_RATING.load(Ordering::SeqCst)
+}
+
+/// Whether posts of every rating are allowed.
+pub fn all_ratings_allowed() -> bool {
+    MAX_RATING.load(Ordering::SeqCst) >= 2
+}
+
+/// Same as `rating_allowed`, for ratings given as text (e.g. "s").
+///
+/// Unknown ratings are only allowed when there is no maximum rating.
+pub fn rating_name_allowed(rating: Option<&str>) -> bool {
+    rating.and_then(parse_rating_level).unwrap_or(2) <= MAX_RATING.load(Ordering::SeqCst)
+}
+
+pub fn rating_name(rating: &PostRating) -> &'static str {
+    match rating {
+        PostRating::Safe => "safe",
+        PostRating::Questionable => "questionable",
+        PostRating::Explicit => "explicit",
+    }
+}
+
+pub fn file_extension(ext: &PostFileExtension) -> &'static str {
+    match ext {
+        PostFileExtension::Jpeg => "jpg",
+        PostFileExtension::Png => "png",
+        PostFileExtension::Gif => "gif",
+        PostFileExtension::Swf => "swf",
+        PostFileExtension::WebM => "webm",
+    }
+}
+
+/// Post metadata written as JSON next to a local file.
+#[derive(Debug, Serialize)]
+pub struct Sidecar {
+    pub id: u64,
+    pub md5: String,
+    pub rating: &'static str,
+    pub artists: Vec<String>,
+    pub tags: Vec<String>,
+    pub sources: Vec<String>,
+    pub description: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comments: Option<Vec<Comment>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notes: Option<Vec<Note>>,
+}
+
+impl From<&Post> for Sidecar {
+    fn from(post: &Post) -> Self {
+        let mut tags = all_tags(post).cloned().collect::<Vec<_>>();
+        tags.sort();
+
+        Sidecar {
+            id: post.id,
+            md5: post.file.md5.clone(),
+            rating: rating_name(&post.rating),
+            artists: post.tags.artist.clone(),
+            tags,
+            sources: post.sources.clone(),
+            description: post.description.clone(),
+            comments: None,
+            notes: None,
+        }
+    }
+}
+
+impl Sidecar {
+    /// Writes the sidecar of `file` to `<file>.json`, returning its path.
+    pub fn write(&self, file: &Path) -> Result<PathBuf> {
+        let mut path = file.as_os_str().to_owned();
+        path.push(".json");
+        let path = PathBuf::from(path);
+
+        serde_json::to_writer_pretty(File::create(&path)?, self)?;
+
+        Ok(path)
+    }
+}
+
 #[derive(Debug)]
 struct DisplayablePost<'a>(&'a Post);
 
@@ -205,15 +919,7 @@ impl fmt::Display for DisplayablePost<'_> {
             }
         }
 
-        writeln!(
-            f,
-            "Rating: {}",
-            match self.0.rating {
-                PostRating::Safe => "safe",
-                PostRating::Questionable => "questionable",
-                PostRating::Explicit => "explicit",
-            }
-        )?;
+        writeln!(f, "Rating: {}", rating_name(&self.0.rating))?;
 
         writeln!(
             f,
@@ -269,17 +975,58 @@ impl fmt::Display for DisplayablePost<'_> {
             writeln!(f, "  [invalid]\n    {}", self.0.tags.invalid.join(" "))?;
         }
 
-        write!(f, "Description: {}", self.0.description)?;
+        write!(
+            f,
+            "Description: {}",
+            dtext::render(&self.0.description, Format::terminal()).trim_end()
+        )?;
 
         Ok(())
     }
 }
 
+// one line summary of a post used in the tree and dot output modes
+fn post_summary(post: &Post) -> String {
+    if post.flags.deleted {
+        format!("#{} (deleted)", post.id)
+    } else if post.tags.artist.is_empty() {
+        format!("#{} ({})", post.id, rating_name(&post.rating))
+    } else {
+        format!(
+            "#{} by {} ({})",
+            post.id,
+            post.tags.artist.join(", "),
+            rating_name(&post.rating)
+        )
+    }
+}
+
+// prints a post and its descendants as an indented tree
+fn print_tree(
+    posts: &[Post],
+    children: &HashMap<u64, Vec<usize>>,
+    i: usize,
+    depth: usize,
+    printed: &mut HashSet<u64>,
+) {
+    let post = &posts[i];
+
+    // relationships can contain cycles
+    if !printed.insert(post.id) {
+        return;
+    }
+
+    println!("{}{}", "  ".repeat(depth), post_summary(post));
+
+    for &child in children.get(&post.id).into_iter().flatten() {
+        print_tree(posts, children, child, depth + 1, printed);
+    }
+}
+
 // output the posts
-pub async fn output_posts(
-    mut posts: impl Stream<Item = Post> + Unpin,
-    mode: OutputMode,
-) -> Result<()> {
+pub async fn output_posts(posts: impl Stream<Item = Post> + Unpin, mode: OutputMode) -> Result<()> {
+    let mut posts = posts.filter(|post| future::ready(rating_allowed(&post.rating)));
+
     match mode {
         OutputMode::Id => {
             while let Some(post) = posts.next().await {
@@ -321,28 +1068,224 @@ pub async fn output_posts(
 
             Ok(())
         }
+
+        OutputMode::Tree => {
+            let posts = posts.collect::<Vec<_>>().await;
+            let ids = posts.iter().map(|p| p.id).collect::<HashSet<_>>();
+
+            // index the posts by parent, only keeping relationships between the given posts
+            let mut children: HashMap<u64, Vec<usize>> = HashMap::new();
+
+            for (i, post) in posts.iter().enumerate() {
+                if let Some(parent) = post.relationships.parent_id {
+                    if ids.contains(&parent) {
+                        children.entry(parent).or_default().push(i);
+                    }
+                }
+            }
+
+            let mut printed = HashSet::new();
+
+            // roots first, then whatever was left out because of a cycle
+            let roots = posts.iter().enumerate().filter(|(_, p)| {
+                !matches!(p.relationships.parent_id, Some(parent) if ids.contains(&parent))
+            });
+
+            for (i, _) in roots {
+                print_tree(&posts, &children, i, 0, &mut printed);
+            }
+
+            for i in 0..posts.len() {
+                print_tree(&posts, &children, i, 0, &mut printed);
+            }
+
+            if posts.is_empty() {
+                println!("No post found.");
+            }
+
+            Ok(())
+        }
+
+        OutputMode::Dot => {
+            let posts = posts.collect::<Vec<_>>().await;
+            let ids = posts.iter().map(|p| p.id).collect::<HashSet<_>>();
+
+            println!("digraph posts {{");
+
+            for post in posts.iter() {
+                println!("    {} [label={:?}];", post.id, post_summary(post));
+            }
+
+            for post in posts.iter() {
+                if let Some(parent) = post.relationships.parent_id {
+                    if ids.contains(&parent) {
+                        println!("    {} -> {};", parent, post.id);
+                    }
+                }
+            }
+
+            println!("}}");
+
+            Ok(())
+        }
     }
 }
 
 // save the posts
-pub async fn save_post(post: &Post, prefix: impl Into<Option<&str>>) -> Result<()> {
-    let id = post.id;
-    let url = post.file.url.as_ref().ok_or(Error::MissingFileUrl)?;
-
-    let mut file = File::create(format!(
+pub async fn save_post(post: &Post, prefix: impl Into<Option<&str>>) -> Result<PathBuf> {
+    let path = PathBuf::from(format!(
         "{}{}.{}",
         prefix.into().unwrap_or(""),
-        id,
-        match post.file.ext {
-            PostFileExtension::Jpeg => "jpg",
-            PostFileExtension::Png => "png",
-            PostFileExtension::Gif => "gif",
-            PostFileExtension::Swf => "swf",
-            PostFileExtension::WebM => "webm",
-        }
-    ))?;
+        post.id,
+        file_extension(&post.file.ext)
+    ));
+
+    save_post_to(post, &path).await?;
+
+    Ok(path)
+}
+
+/// Downloads the file of a post to the given path.
+pub async fn save_post_to(post: &Post, path: &Path) -> Result<()> {
+    if !rating_allowed(&post.rating) {
+        return Err(Error::RatingNotAllowed(rating_name(&post.rating)));
+    }
+
+    let url = post.file.url.as_ref().ok_or(Error::MissingFileUrl)?;
+    let mut file = File::create(path)?;
 
     download(url, &mut file).await?;
 
     Ok(())
 }
+
+/// Expands a file name template such as `{artist}/{id}.{ext}` for a post.
+///
+/// Known placeholders are `{id}`, `{md5}`, `{ext}`, `{rating}` and `{artist}`.
+pub fn format_file_name(template: &str, post: &Post) -> String {
+    // artist names must not add folders to the path
+    let artist = post
+        .tags
+        .artist
+        .first()
+        .map_or("unknown_artist", String::as_str)
+        .replace(['/', '\\'], "_");
+
+    template
+        .replace("{id}", &post.id.to_string())
+        .replace("{md5}", &post.file.md5)
+        .replace("{ext}", file_extension(&post.file.ext))
+        .replace("{rating}", rating_name(&post.rating))
+        .replace("{artist}", &artist)
+}
+
+/// Saves a post with `save_post`, along with its comments and notes if asked to.
+pub async fn save_post_with_extras(
+    url: &str,
+    post: &Post,
+    prefix: impl Into<Option<&str>>,
+    extras: &SaveExtras,
+) -> Result<()> {
+    let path = save_post(post, prefix).await?;
+    let mut sidecar = Sidecar::from(post);
+
+    if extras.comments {
+        sidecar.comments = Some(comments::get_comments(url, post.id).await?);
+    }
+
+    // a failure to burn the notes doesn't prevent the sidecar from being written
+    let mut burned = Ok(());
+
+    match extras.notes {
+        Some(NotesMode::Sidecar) => sidecar.notes = Some(notes::get_notes(url, post.id).await?),
+        Some(NotesMode::Burn) => {
+            let post_notes = notes::get_notes(url, post.id).await?;
+
+            let drawable = !matches!(
+                post.file.ext,
+                PostFileExtension::Swf | PostFileExtension::WebM
+            );
+
+            if !post_notes.is_empty() && !drawable {
+                eprintln!(
+                    "Notes of #{} not burned: {} files can't be drawn on",
+                    post.id,
+                    file_extension(&post.file.ext)
+                );
+            } else if !post_notes.is_empty() {
+                burned = notes::load_font(extras.font.as_deref())
+                    .and_then(|font| notes::burn(&path, &post_notes, &font).map(drop));
+            }
+        }
+        None => (),
+    }
+
+    if sidecar.comments.is_some() || sidecar.notes.is_some() {
+        sidecar.write(&path)?;
+    }
+
+    burned
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, process};
+
+    // writes a configuration file in the temporary directory
+    fn config_file(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("get621-{}-{}.toml", process::id(), name));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn missing_config_is_empty() {
+        let config = Config::load(Path::new("/nonexistent/get621.toml")).unwrap();
+
+        assert!(config.max_rating.is_none());
+        assert_eq!(config.max_rating_level(None), 2);
+        assert_eq!(config.max_rating_level(Some("questionable")), 1);
+    }
+
+    #[test]
+    fn max_rating_cannot_loosen_config_lock() {
+        let path = config_file("lock", "max-rating = \"safe\"\n");
+        let config = Config::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(config.max_rating_level(None), 0);
+        assert_eq!(config.max_rating_level(Some("explicit")), 0);
+        assert_eq!(config.max_rating_level(Some("questionable")), 0);
+    }
+
+    #[test]
+    fn max_rating_can_tighten_config_lock() {
+        let config = Config {
+            max_rating: Some("questionable".into()),
+        };
+
+        assert_eq!(config.max_rating_level(Some("safe")), 0);
+        assert_eq!(config.max_rating_level(Some("explicit")), 1);
+    }
+
+    #[test]
+    fn rejects_invalid_config() {
+        for (name, contents) in &[
+            ("rating", "max-rating = \"sfw\"\n"),
+            ("key", "max-ratings = \"safe\"\n"),
+            ("syntax", "max-rating = safe\n"),
+        ] {
+            let path = config_file(name, contents);
+            let result = Config::load(&path);
+            fs::remove_file(&path).unwrap();
+
+            assert!(
+                matches!(result, Err(Error::ConfigError(..))),
+                "{}: {:?}",
+                name,
+                result
+            );
+        }
+    }
+}
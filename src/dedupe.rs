@@ -0,0 +1,300 @@
+use crate::{
+    common::{expand_paths, valid_parse, Result},
+    index::{self, HashKind, ImageHashes},
+};
+use clap::{App, Arg, ArgMatches, SubCommand};
+use image::GenericImageView;
+use serde::Serialize;
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("dedupe")
+        .about("Find duplicate and near-duplicate files")
+        .arg(
+            Arg::with_name("source")
+                .index(1)
+                .required(true)
+                .multiple(true)
+                .allow_hyphen_values(true)
+                .help("Files or folders to compare; can be a glob pattern"),
+        )
+        .arg(
+            Arg::with_name("similarity")
+                .short("S")
+                .long("similarity")
+                .takes_value(true)
+                .default_value("90")
+                .validator(|v| valid_parse::<f64>(&v, "Must be a floating point value."))
+                .help("Set the similarity threshold for near-duplicates (in percents)"),
+        )
+        .arg(
+            Arg::with_name("hash")
+                .long("hash")
+                .takes_value(true)
+                .default_value("dhash")
+                .possible_values(&["ahash", "dhash", "phash"])
+                .help("Set the perceptual hash algorithm used to compare images"),
+        )
+        .arg(
+            Arg::with_name("keep")
+                .short("k")
+                .long("keep")
+                .takes_value(true)
+                .default_value("largest")
+                .possible_values(&["largest", "resolution"])
+                .help("Set which file of each group is kept when using --trash"),
+        )
+        .arg(
+            Arg::with_name("trash")
+                .short("t")
+                .long("trash")
+                .takes_value(true)
+                .help("Move every file of a group but the kept one to this folder"),
+        )
+        .arg(
+            Arg::with_name("output_mode")
+                .short("o")
+                .long("output")
+                .takes_value(true)
+                .default_value("text")
+                .possible_values(&["text", "json"])
+                .help("Set output mode; one of: text, json"),
+        )
+}
+
+#[derive(Debug, Serialize)]
+struct FileInfo {
+    path: PathBuf,
+    md5: String,
+    size: u64,
+    width: Option<u32>,
+    height: Option<u32>,
+    #[serde(skip)]
+    hashes: Option<ImageHashes>,
+    /// Where the file was moved to with `--trash`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    moved_to: Option<PathBuf>,
+}
+
+impl FileInfo {
+    fn read(path: PathBuf) -> Result<Self> {
+        let bytes = fs::read(&path)?;
+
+        // files that aren't images (e.g. videos) can only be exact duplicates
+        let img = image::load_from_memory(&bytes).ok();
+
+        Ok(FileInfo {
+            md5: format!("{:x}", md5::compute(&bytes)),
+            size: bytes.len() as u64,
+            width: img.as_ref().map(|img| img.width()),
+            height: img.as_ref().map(|img| img.height()),
+            hashes: img.as_ref().map(ImageHashes::of),
+            moved_to: None,
+            path,
+        })
+    }
+
+    fn resolution(&self) -> u64 {
+        u64::from(self.width.unwrap_or(0)) * u64::from(self.height.unwrap_or(0))
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Group {
+    /// `true` when every file of the group has the same MD5.
+    exact: bool,
+    files: Vec<FileInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    kept: Option<PathBuf>,
+}
+
+// minimal union-find to merge files into groups
+struct DisjointSet(Vec<usize>);
+
+impl DisjointSet {
+    fn new(len: usize) -> Self {
+        DisjointSet((0..len).collect())
+    }
+
+    fn find(&mut self, i: usize) -> usize {
+        if self.0[i] != i {
+            let root = self.find(self.0[i]);
+            self.0[i] = root;
+        }
+
+        self.0[i]
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        self.0[a] = b;
+    }
+}
+
+// groups files having the same MD5 or similar perceptual hashes
+fn group_files(files: Vec<FileInfo>, kind: HashKind, min_similarity: f64) -> Vec<Group> {
+    let mut set = DisjointSet::new(files.len());
+
+    // exact duplicates
+    let mut by_md5 = HashMap::new();
+
+    for (i, file) in files.iter().enumerate() {
+        if let Some(&first) = by_md5.get(&file.md5) {
+            set.union(i, first);
+        } else {
+            by_md5.insert(file.md5.clone(), i);
+        }
+    }
+
+    // near-duplicates, only comparing one file per MD5
+    let hashed = by_md5
+        .values()
+        .filter_map(|&i| Some((i, files[i].hashes?.get(kind))))
+        .collect::<Vec<_>>();
+
+    for (n, &(a, hash_a)) in hashed.iter().enumerate() {
+        for &(b, hash_b) in hashed[n + 1..].iter() {
+            if index::similarity(hash_a, hash_b) >= min_similarity {
+                set.union(a, b);
+            }
+        }
+    }
+
+    let mut groups: HashMap<usize, Vec<FileInfo>> = HashMap::new();
+
+    for (i, file) in files.into_iter().enumerate() {
+        groups.entry(set.find(i)).or_default().push(file);
+    }
+
+    let mut groups = groups
+        .into_values()
+        .filter(|files| files.len() > 1)
+        .map(|mut files| {
+            files.sort_by(|a, b| a.path.cmp(&b.path));
+
+            Group {
+                exact: files.iter().all(|f| f.md5 == files[0].md5),
+                files,
+                kept: None,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    groups.sort_by(|a, b| a.files[0].path.cmp(&b.files[0].path));
+    groups
+}
+
+// moves a file into the trash folder without overwriting anything
+fn move_to_trash(path: &Path, trash: &Path) -> Result<PathBuf> {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let mut dest = trash.join(&*file_name);
+    let mut n = 1;
+
+    while dest.exists() {
+        dest = trash.join(format!("{}_{}", n, file_name));
+        n += 1;
+    }
+
+    // renaming fails across file systems
+    if fs::rename(path, &dest).is_err() {
+        fs::copy(path, &dest)?;
+        fs::remove_file(path)?;
+    }
+
+    Ok(dest)
+}
+
+// get621 dedupe ...
+pub fn run(matches: &ArgMatches<'_>) -> Result<()> {
+    let arg_source = matches.values_of("source").unwrap().collect::<Vec<_>>();
+    let arg_similarity = matches.value_of("similarity").unwrap().parse().unwrap();
+    let arg_hash = matches.value_of("hash").unwrap().into();
+    let arg_keep = matches.value_of("keep").unwrap();
+    let arg_trash = matches.value_of("trash").map(Path::new);
+    let json = matches.value_of("output_mode") == Some("json");
+
+    let mut files = Vec::new();
+    let mut seen = HashSet::new();
+
+    // the same file can be given several times, e.g. by overlapping patterns
+    for path in expand_paths(&arg_source)? {
+        if !seen.insert(path.clone()) {
+            continue;
+        }
+
+        match FileInfo::read(path.clone()) {
+            Ok(info) => files.push(info),
+            Err(e) => eprintln!("Skipping {}: {}", path.display(), e),
+        }
+    }
+
+    let mut groups = group_files(files, arg_hash, arg_similarity);
+
+    if let Some(trash) = arg_trash {
+        fs::create_dir_all(trash)?;
+
+        for group in groups.iter_mut() {
+            let kept = match arg_keep {
+                "resolution" => group.files.iter().max_by_key(|f| (f.resolution(), f.size)),
+                _ => group.files.iter().max_by_key(|f| (f.size, f.resolution())),
+            }
+            .map(|f| f.path.clone());
+
+            for file in group.files.iter_mut() {
+                if Some(&file.path) != kept.as_ref() {
+                    match move_to_trash(&file.path, trash) {
+                        Ok(dest) => file.moved_to = Some(dest),
+                        Err(e) => eprintln!("Couldn't move {}: {}", file.path.display(), e),
+                    }
+                }
+            }
+
+            group.kept = kept;
+        }
+    }
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&groups)?);
+    } else if groups.is_empty() {
+        println!("No duplicate found.");
+    } else {
+        for (i, group) in groups.iter().enumerate() {
+            if i > 0 {
+                println!("----------------");
+            }
+
+            println!(
+                "Group {} ({} {})",
+                i + 1,
+                group.files.len(),
+                if group.exact {
+                    "identical files"
+                } else {
+                    "similar files"
+                }
+            );
+
+            for file in group.files.iter() {
+                let mark = match group.kept {
+                    Some(ref kept) if kept == &file.path => "[kept] ",
+                    _ if file.moved_to.is_some() => "[moved] ",
+                    _ => "",
+                };
+
+                print!("  {}{} ({} bytes", mark, file.path.display(), file.size);
+
+                if let (Some(width), Some(height)) = (file.width, file.height) {
+                    print!(", {}x{}", width, height);
+                }
+
+                println!(", MD5: {})", file.md5);
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,862 @@
+//! Parser and renderers for DText, the markup language of e621 descriptions, comments, wiki
+//! pages and forum posts.
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::{borrow::Cow, mem, str::FromStr};
+
+/// Inline formatting.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Style {
+    Bold,
+    Italic,
+    Underline,
+    Strikethrough,
+    Spoiler,
+    Superscript,
+    Subscript,
+    Color,
+}
+
+impl Style {
+    fn from_tag(tag: &str) -> Self {
+        match tag.to_lowercase().as_str() {
+            "b" => Style::Bold,
+            "i" => Style::Italic,
+            "u" => Style::Underline,
+            "s" => Style::Strikethrough,
+            "spoiler" => Style::Spoiler,
+            "sup" => Style::Superscript,
+            "sub" => Style::Subscript,
+            _ => Style::Color,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Inline {
+    Text(String),
+    LineBreak,
+    Styled(Style, Vec<Inline>),
+    /// `"text":url`, bare URLs and references such as `post #123`.
+    Link {
+        url: String,
+        text: String,
+    },
+    /// `[[page]]` or `[[page|label]]`.
+    WikiLink {
+        page: String,
+        label: String,
+    },
+    /// `{{tags}}` or `{{tags|label}}`.
+    SearchLink {
+        query: String,
+        label: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Block {
+    Paragraph(Vec<Inline>),
+    Header(u8, Vec<Inline>),
+    /// Items of a list along with their depth, starting at 1.
+    List(Vec<(usize, Vec<Inline>)>),
+    Quote(Vec<Block>),
+    Code(String),
+    Section {
+        title: Option<String>,
+        blocks: Vec<Block>,
+    },
+}
+
+// adds text to a list of inlines, turning line feeds into line breaks
+fn push_text(inlines: &mut Vec<Inline>, text: &str) {
+    for (i, line) in text.split('\n').enumerate() {
+        if i > 0 {
+            inlines.push(Inline::LineBreak);
+        }
+
+        if !line.is_empty() {
+            inlines.push(Inline::Text(line.into()));
+        }
+    }
+}
+
+// removes the control characters (e.g. terminal escape sequences) other than tabs and line feeds
+fn strip_control_chars(text: &str) -> Cow<'_, str> {
+    if text
+        .chars()
+        .any(|c| c.is_control() && c != '\n' && c != '\t')
+    {
+        text.chars()
+            .filter(|&c| !c.is_control() || c == '\n' || c == '\t')
+            .collect::<String>()
+            .into()
+    } else {
+        text.into()
+    }
+}
+
+// splits trailing punctuation, which is most likely not part of a URL
+fn split_url(url: &str) -> (&str, &str) {
+    let mut end = url.len();
+
+    while let Some(c) = url[..end].chars().last() {
+        // closing parentheses are kept if they match one in the URL (e.g. Wikipedia links)
+        let unbalanced = c == ')' && {
+            let url = &url[..end];
+            url.matches('(').count() < url.matches(')').count()
+        };
+
+        if unbalanced || (c != ')' && ".,:;!?'".contains(c)) {
+            end -= c.len_utf8();
+        } else {
+            break;
+        }
+    }
+
+    url.split_at(end)
+}
+
+// whether a link target is safe to render, i.e. a web page, a page of the site or an anchor
+fn allowed_url(url: &str) -> bool {
+    let lower = url.to_lowercase();
+
+    lower.starts_with("http://")
+        || lower.starts_with("https://")
+        || (url.starts_with('/') && !url.starts_with("//"))
+        || url.starts_with('#')
+}
+
+/// Parses the inline markup of a piece of DText.
+///
+/// Control characters are removed, so that the text can't mess with the terminal.
+pub fn parse_inline(text: &str) -> Vec<Inline> {
+    lazy_static! {
+        static ref INLINE_REGEX: Regex = Regex::new(concat!(
+            r"(?i)\[(?P<close>/)?(?P<tag>b|i|u|s|sup|sub|spoiler|color)(?:=[^\]]*)?\]",
+            r"|\[\[(?P<page>[^\]|]+)(?:\|(?P<page_label>[^\]]*))?\]\]",
+            r"|\{\{(?P<query>[^}|]+)(?:\|(?P<query_label>[^}]*))?\}\}",
+            r#"|"(?P<link_text>[^"]+)":(?:\[(?P<bracketed_url>[^\]]+)\]"#,
+            r"|(?P<link_url>(?:https?://|/)[^\s\[\]<>]+))",
+            r"|\b(?P<kind>post|pool|comment|forum|topic|set|user) #(?P<id>\d+)",
+            r#"|(?P<url>https?://[^\s\[\]<>"]+)"#,
+        ))
+        .unwrap();
+    }
+
+    let text = &*strip_control_chars(text);
+
+    // formatting tags that haven't been closed yet, with the inlines preceding them
+    let mut stack: Vec<(Style, Vec<Inline>)> = Vec::new();
+    let mut current = Vec::new();
+    let mut last = 0;
+
+    for caps in INLINE_REGEX.captures_iter(text) {
+        let whole = caps.get(0).unwrap();
+        push_text(&mut current, &text[last..whole.start()]);
+        last = whole.end();
+
+        if let Some(tag) = caps.name("tag") {
+            let style = Style::from_tag(tag.as_str());
+
+            if caps.name("close").is_none() {
+                stack.push((style, mem::take(&mut current)));
+            } else if let Some(i) = stack.iter().rposition(|(s, _)| *s == style) {
+                // tags opened inside of this one are closed with it
+                while stack.len() > i {
+                    let (style, parent) = stack.pop().unwrap();
+                    let inner = mem::replace(&mut current, parent);
+                    current.push(Inline::Styled(style, inner));
+                }
+            } else {
+                push_text(&mut current, whole.as_str());
+            }
+        } else if let Some(page) = caps.name("page") {
+            current.push(Inline::WikiLink {
+                page: page.as_str().trim().into(),
+                label: caps
+                    .name("page_label")
+                    .unwrap_or(page)
+                    .as_str()
+                    .trim()
+                    .into(),
+            });
+        } else if let Some(query) = caps.name("query") {
+            current.push(Inline::SearchLink {
+                query: query.as_str().trim().into(),
+                label: caps
+                    .name("query_label")
+                    .unwrap_or(query)
+                    .as_str()
+                    .trim()
+                    .into(),
+            });
+        } else if let Some(link_text) = caps.name("link_text") {
+            let (url, rest) = match caps.name("bracketed_url") {
+                Some(url) => (url.as_str(), ""),
+                None => split_url(caps.name("link_url").unwrap().as_str()),
+            };
+
+            if allowed_url(url) {
+                current.push(Inline::Link {
+                    url: url.into(),
+                    text: link_text.as_str().into(),
+                });
+            } else {
+                push_text(&mut current, link_text.as_str());
+            }
+
+            push_text(&mut current, rest);
+        } else if let (Some(kind), Some(id)) = (caps.name("kind"), caps.name("id")) {
+            let path = match kind.as_str().to_lowercase().as_str() {
+                "post" => "posts",
+                "pool" => "pools",
+                "comment" => "comments",
+                "forum" => "forum_posts",
+                "topic" => "forum_topics",
+                "set" => "post_sets",
+                _ => "users",
+            };
+
+            current.push(Inline::Link {
+                url: format!("/{}/{}", path, id.as_str()),
+                text: whole.as_str().into(),
+            });
+        } else if let Some(url) = caps.name("url") {
+            let (url, rest) = split_url(url.as_str());
+
+            current.push(Inline::Link {
+                url: url.into(),
+                text: url.into(),
+            });
+            push_text(&mut current, rest);
+        }
+    }
+
+    push_text(&mut current, &text[last..]);
+
+    // unclosed tags apply to the end of the text
+    while let Some((style, parent)) = stack.pop() {
+        let inner = mem::replace(&mut current, parent);
+        current.push(Inline::Styled(style, inner));
+    }
+
+    current
+}
+
+// turns the pending paragraph or list into a block
+fn flush(blocks: &mut Vec<Block>, paragraph: &mut Vec<&str>, list: &mut Vec<(usize, Vec<Inline>)>) {
+    if !list.is_empty() {
+        blocks.push(Block::List(mem::take(list)));
+    }
+
+    if !paragraph.is_empty() {
+        blocks.push(Block::Paragraph(parse_inline(&paragraph.join("\n"))));
+        paragraph.clear();
+    }
+}
+
+// parses lines into blocks until the given closing tag
+fn parse_blocks(lines: &[&str], pos: &mut usize, closing_tag: Option<&str>) -> Vec<Block> {
+    lazy_static! {
+        static ref HEADER_REGEX: Regex = Regex::new(r"^h([1-6])(?:#[\w-]+)?\.\s*(.*)$").unwrap();
+        static ref LIST_REGEX: Regex = Regex::new(r"^(\*+)\s+(.*)$").unwrap();
+        static ref SECTION_REGEX: Regex =
+            Regex::new(r"(?i)^\[section(?:,expanded)?(?:=([^\]]*))?\]$").unwrap();
+    }
+
+    let mut blocks = Vec::new();
+    let mut paragraph = Vec::new();
+    let mut list = Vec::new();
+
+    while *pos < lines.len() {
+        let line = lines[*pos];
+        let trimmed = line.trim();
+        *pos += 1;
+
+        if matches!(closing_tag, Some(tag) if trimmed.eq_ignore_ascii_case(tag)) {
+            break;
+        }
+
+        if trimmed.eq_ignore_ascii_case("[quote]") {
+            flush(&mut blocks, &mut paragraph, &mut list);
+            blocks.push(Block::Quote(parse_blocks(lines, pos, Some("[/quote]"))));
+        } else if trimmed.eq_ignore_ascii_case("[code]") {
+            flush(&mut blocks, &mut paragraph, &mut list);
+
+            let mut code = Vec::new();
+
+            while *pos < lines.len() && !lines[*pos].trim().eq_ignore_ascii_case("[/code]") {
+                code.push(lines[*pos]);
+                *pos += 1;
+            }
+
+            *pos += 1;
+            blocks.push(Block::Code(code.join("\n")));
+        } else if let Some(caps) = SECTION_REGEX.captures(trimmed) {
+            flush(&mut blocks, &mut paragraph, &mut list);
+            blocks.push(Block::Section {
+                title: caps.get(1).map(|title| title.as_str().trim().into()),
+                blocks: parse_blocks(lines, pos, Some("[/section]")),
+            });
+        } else if let Some(caps) = HEADER_REGEX.captures(trimmed) {
+            flush(&mut blocks, &mut paragraph, &mut list);
+            blocks.push(Block::Header(
+                caps[1].parse().unwrap(),
+                parse_inline(&caps[2]),
+            ));
+        } else if let Some(caps) = LIST_REGEX.captures(trimmed) {
+            flush(&mut blocks, &mut paragraph, &mut Vec::new());
+            list.push((caps[1].len(), parse_inline(&caps[2])));
+        } else if trimmed.is_empty() {
+            flush(&mut blocks, &mut paragraph, &mut list);
+        } else {
+            // a list ends with the first line that isn't an item
+            flush(&mut blocks, &mut Vec::new(), &mut list);
+            paragraph.push(line);
+        }
+    }
+
+    flush(&mut blocks, &mut paragraph, &mut list);
+    blocks
+}
+
+/// Parses a piece of DText.
+///
+/// Control characters are removed, so that the text can't mess with the terminal.
+pub fn parse(dtext: &str) -> Vec<Block> {
+    lazy_static! {
+        static ref BLOCK_TAG_REGEX: Regex = Regex::new(
+            r"(?i)\n?[ \t]*(\[/?(?:quote|code|section(?:,expanded)?(?:=[^\]]*)?)\])[ \t]*\n?"
+        )
+        .unwrap();
+    }
+
+    // block tags are often written on the same line as the text, put them on their own line
+    // (the line feeds around them are replaced so that no empty line is added to code blocks)
+    let dtext = strip_control_chars(dtext);
+    let dtext = BLOCK_TAG_REGEX.replace_all(&dtext, "\n$1\n");
+    let lines = dtext.lines().collect::<Vec<_>>();
+
+    parse_blocks(&lines, &mut 0, None)
+}
+
+/// Output formats of the DText renderer.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Format {
+    /// Text with ANSI escape sequences, for terminals.
+    Ansi,
+    Plain,
+    Html,
+    Markdown,
+}
+
+impl Format {
+    /// `Ansi` if the standard output is a terminal, `Plain` otherwise.
+    pub fn terminal() -> Self {
+        if atty::is(atty::Stream::Stdout) {
+            Format::Ansi
+        } else {
+            Format::Plain
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ansi" => Ok(Format::Ansi),
+            "plain" => Ok(Format::Plain),
+            "html" => Ok(Format::Html),
+            "markdown" => Ok(Format::Markdown),
+            _ => Err(format!("Invalid format: {}", s)),
+        }
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        if "\\`*_[]<>#|~".contains(c) {
+            escaped.push('\\');
+        }
+
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+// percent-encodes the characters that would end a Markdown link target or start HTML
+fn escape_markdown_url(url: &str) -> String {
+    let mut escaped = String::with_capacity(url.len());
+
+    for c in url.chars() {
+        match c {
+            ' ' | '(' | ')' | '<' | '>' | '"' | '\\' | '`' => {
+                escaped.push_str(&format!("%{:02X}", c as u8))
+            }
+            _ => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+// a code fence longer than any run of backticks in the code
+fn markdown_fence(code: &str) -> String {
+    let longest = code.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+
+    "`".repeat(longest.max(2) + 1)
+}
+
+// percent-encodes a search query for a URL
+fn encode_query(query: &str) -> String {
+    let mut encoded = String::with_capacity(query.len());
+
+    for b in query.bytes() {
+        match b {
+            b' ' => encoded.push('+'),
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'_' | b'-' | b'.' | b'~' => {
+                encoded.push(b as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+
+    encoded
+}
+
+fn wiki_url(page: &str) -> String {
+    format!(
+        "/wiki_pages/show_or_new?title={}",
+        encode_query(&page.to_lowercase().replace(' ', "_"))
+    )
+}
+
+fn search_url(query: &str) -> String {
+    format!("/posts?tags={}", encode_query(query))
+}
+
+// ANSI escape sequences enabling and disabling a style without affecting the others
+fn ansi_codes(style: Style) -> (&'static str, &'static str) {
+    match style {
+        Style::Bold => ("\x1b[1m", "\x1b[22m"),
+        Style::Italic => ("\x1b[3m", "\x1b[23m"),
+        Style::Underline => ("\x1b[4m", "\x1b[24m"),
+        Style::Strikethrough => ("\x1b[9m", "\x1b[29m"),
+        Style::Spoiler => ("\x1b[7m", "\x1b[27m"),
+        Style::Superscript | Style::Subscript | Style::Color => ("", ""),
+    }
+}
+
+fn html_tags(style: Style) -> (&'static str, &'static str) {
+    match style {
+        Style::Bold => ("<strong>", "</strong>"),
+        Style::Italic => ("<em>", "</em>"),
+        Style::Underline => ("<u>", "</u>"),
+        Style::Strikethrough => ("<s>", "</s>"),
+        Style::Spoiler => ("<span class=\"spoiler\">", "</span>"),
+        Style::Superscript => ("<sup>", "</sup>"),
+        Style::Subscript => ("<sub>", "</sub>"),
+        Style::Color => ("<span>", "</span>"),
+    }
+}
+
+fn markdown_tags(style: Style) -> (&'static str, &'static str) {
+    match style {
+        Style::Bold => ("**", "**"),
+        Style::Italic => ("*", "*"),
+        Style::Underline => ("<u>", "</u>"),
+        Style::Strikethrough => ("~~", "~~"),
+        Style::Superscript => ("<sup>", "</sup>"),
+        Style::Subscript => ("<sub>", "</sub>"),
+        Style::Spoiler | Style::Color => ("", ""),
+    }
+}
+
+fn render_inlines(inlines: &[Inline], format: Format, out: &mut String) {
+    for inline in inlines {
+        match (inline, format) {
+            (Inline::Text(text), Format::Html) => out.push_str(&escape_html(text)),
+            (Inline::Text(text), Format::Markdown) => out.push_str(&escape_markdown(text)),
+            (Inline::Text(text), _) => out.push_str(text),
+
+            (Inline::LineBreak, Format::Html) => out.push_str("<br>\n"),
+            (Inline::LineBreak, Format::Markdown) => out.push_str("  \n"),
+            (Inline::LineBreak, _) => out.push('\n'),
+
+            (Inline::Styled(style, inner), _) => {
+                let (open, close) = match format {
+                    Format::Ansi => ansi_codes(*style),
+                    Format::Plain => ("", ""),
+                    Format::Html => html_tags(*style),
+                    Format::Markdown => markdown_tags(*style),
+                };
+
+                out.push_str(open);
+                render_inlines(inner, format, out);
+                out.push_str(close);
+            }
+
+            (Inline::Link { url, text }, Format::Html) => out.push_str(&format!(
+                "<a href=\"{}\">{}</a>",
+                escape_html(url),
+                escape_html(text)
+            )),
+            (Inline::Link { url, text }, Format::Markdown) => out.push_str(&format!(
+                "[{}]({})",
+                escape_markdown(text),
+                escape_markdown_url(url)
+            )),
+            (Inline::Link { url, text }, _) if url == text => out.push_str(url),
+            (Inline::Link { url, text }, Format::Ansi) => {
+                out.push_str(&format!("\x1b[4m{}\x1b[24m <{}>", text, url))
+            }
+            (Inline::Link { url, text }, _) => out.push_str(&format!("{} <{}>", text, url)),
+
+            (Inline::WikiLink { page, label }, Format::Html) => out.push_str(&format!(
+                "<a href=\"{}\">{}</a>",
+                escape_html(&wiki_url(page)),
+                escape_html(label)
+            )),
+            (Inline::WikiLink { page, label }, Format::Markdown) => {
+                out.push_str(&format!("[{}]({})", escape_markdown(label), wiki_url(page)))
+            }
+            (Inline::SearchLink { query, label }, Format::Html) => out.push_str(&format!(
+                "<a href=\"{}\">{}</a>",
+                escape_html(&search_url(query)),
+                escape_html(label)
+            )),
+            (Inline::SearchLink { query, label }, Format::Markdown) => out.push_str(&format!(
+                "[{}]({})",
+                escape_markdown(label),
+                search_url(query)
+            )),
+            (Inline::WikiLink { label, .. }, Format::Ansi)
+            | (Inline::SearchLink { label, .. }, Format::Ansi) => {
+                out.push_str(&format!("\x1b[36m{}\x1b[39m", label))
+            }
+            (Inline::WikiLink { label, .. }, _) | (Inline::SearchLink { label, .. }, _) => {
+                out.push_str(label)
+            }
+        }
+    }
+}
+
+// prefixes every line of a piece of text
+fn indent(text: &str, prefix: &str) -> String {
+    text.lines()
+        .map(|line| {
+            if line.is_empty() {
+                format!("{}\n", prefix.trim_end())
+            } else {
+                format!("{}{}\n", prefix, line)
+            }
+        })
+        .collect()
+}
+
+fn render_block(block: &Block, format: Format) -> String {
+    let inline = |inlines: &[Inline]| {
+        let mut out = String::new();
+        render_inlines(inlines, format, &mut out);
+        out
+    };
+
+    match (block, format) {
+        (Block::Paragraph(inlines), Format::Html) => format!("<p>{}</p>\n", inline(inlines)),
+        (Block::Paragraph(inlines), _) => format!("{}\n", inline(inlines)),
+
+        (Block::Header(level, inlines), Format::Html) => {
+            format!("<h{0}>{1}</h{0}>\n", level, inline(inlines))
+        }
+        (Block::Header(level, inlines), Format::Markdown) => {
+            format!("{} {}\n", "#".repeat(*level as usize), inline(inlines))
+        }
+        (Block::Header(_, inlines), Format::Ansi) => {
+            format!("\x1b[1m{}\x1b[22m\n", inline(inlines))
+        }
+        (Block::Header(level, inlines), _) => {
+            let title = inline(inlines);
+            let underline = if *level <= 2 { "=" } else { "-" };
+
+            format!("{}\n{}\n", title, underline.repeat(title.chars().count()))
+        }
+
+        (Block::List(items), Format::Html) => {
+            let mut out = String::new();
+            let mut depth = 0;
+
+            for (item_depth, inlines) in items {
+                while depth < *item_depth {
+                    out.push_str("<ul>\n");
+                    depth += 1;
+                }
+
+                while depth > *item_depth {
+                    out.push_str("</ul>\n");
+                    depth -= 1;
+                }
+
+                out.push_str(&format!("<li>{}</li>\n", inline(inlines)));
+            }
+
+            out.push_str(&"</ul>\n".repeat(depth));
+            out
+        }
+        (Block::List(items), _) => items
+            .iter()
+            .map(|(depth, inlines)| format!("{}- {}\n", "  ".repeat(depth - 1), inline(inlines)))
+            .collect(),
+
+        (Block::Quote(blocks), Format::Html) => {
+            format!(
+                "<blockquote>\n{}</blockquote>\n",
+                render_blocks(blocks, format)
+            )
+        }
+        (Block::Quote(blocks), Format::Ansi) => {
+            indent(&render_blocks(blocks, format), "\x1b[2m│\x1b[22m ")
+        }
+        (Block::Quote(blocks), _) => indent(&render_blocks(blocks, format), "> "),
+
+        (Block::Code(code), Format::Html) => {
+            format!("<pre><code>{}</code></pre>\n", escape_html(code))
+        }
+        (Block::Code(code), Format::Markdown) => {
+            let fence = markdown_fence(code);
+            format!("{0}\n{1}\n{0}\n", fence, code)
+        }
+        (Block::Code(code), _) => indent(code, "    "),
+
+        (Block::Section { title, blocks }, Format::Html) => format!(
+            "<details>\n<summary>{}</summary>\n{}</details>\n",
+            escape_html(title.as_deref().unwrap_or("")),
+            render_blocks(blocks, format)
+        ),
+        (Block::Section { title, blocks }, Format::Markdown) => format!(
+            "**{}**\n\n{}",
+            escape_markdown(title.as_deref().unwrap_or("")),
+            render_blocks(blocks, format)
+        ),
+        (Block::Section { title, blocks }, Format::Ansi) => format!(
+            "\x1b[1m{}\x1b[22m\n{}",
+            title.as_deref().unwrap_or(""),
+            indent(&render_blocks(blocks, format), "  ")
+        ),
+        (Block::Section { title, blocks }, _) => format!(
+            "{}\n{}",
+            title.as_deref().unwrap_or(""),
+            indent(&render_blocks(blocks, format), "  ")
+        ),
+    }
+}
+
+/// Renders parsed DText.
+pub fn render_blocks(blocks: &[Block], format: Format) -> String {
+    blocks
+        .iter()
+        .map(|block| render_block(block, format))
+        .collect::<Vec<_>>()
+        .join(if format == Format::Html { "" } else { "\n" })
+}
+
+/// Renders a piece of DText in the given format.
+pub fn render(dtext: &str, format: Format) -> String {
+    render_blocks(&parse(dtext), format)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(s: &str) -> Inline {
+        Inline::Text(s.into())
+    }
+
+    fn link(url: &str, text: &str) -> Inline {
+        Inline::Link {
+            url: url.into(),
+            text: text.into(),
+        }
+    }
+
+    #[test]
+    fn parses_nested_styles() {
+        assert_eq!(
+            parse_inline("[b]bold [i]both[/i][/b] [u]unclosed"),
+            vec![
+                Inline::Styled(
+                    Style::Bold,
+                    vec![
+                        text("bold "),
+                        Inline::Styled(Style::Italic, vec![text("both")])
+                    ]
+                ),
+                text(" "),
+                Inline::Styled(Style::Underline, vec![text("unclosed")]),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_links() {
+        assert_eq!(
+            parse_inline(r#"see "this":/posts/1, post #2 and [[some page|it]]."#),
+            vec![
+                text("see "),
+                link("/posts/1", "this"),
+                text(","),
+                text(" "),
+                link("/posts/2", "post #2"),
+                text(" and "),
+                Inline::WikiLink {
+                    page: "some page".into(),
+                    label: "it".into(),
+                },
+                text("."),
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_balanced_parentheses_in_urls() {
+        assert_eq!(
+            parse_inline("(https://en.wikipedia.org/wiki/Fox_(disambiguation))."),
+            vec![
+                text("("),
+                link(
+                    "https://en.wikipedia.org/wiki/Fox_(disambiguation)",
+                    "https://en.wikipedia.org/wiki/Fox_(disambiguation)"
+                ),
+                text(")."),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_unsafe_link_targets() {
+        for dtext in &[
+            r#""click":[javascript:alert(1)]"#,
+            r#""click":[data:text/html,hi]"#,
+            r#""click":[//example.com]"#,
+        ] {
+            assert_eq!(parse_inline(dtext), vec![text("click")], "{}", dtext);
+            assert!(!render(dtext, Format::Html).contains("href"), "{}", dtext);
+            assert_eq!(render(dtext, Format::Markdown), "click\n", "{}", dtext);
+        }
+
+        assert!(!render(r#""x":javascript:alert(1)"#, Format::Html).contains("href"));
+
+        // link targets can't close the Markdown link
+        assert_eq!(
+            render(
+                r#""x":[https://a.com/) <img src=x onerror=alert(1)>]"#,
+                Format::Markdown
+            ),
+            "[x](https://a.com/%29%20%3Cimg%20src=x%20onerror=alert%281%29%3E)\n"
+        );
+
+        // nor can code close its fence
+        assert_eq!(
+            render(
+                "[code]a\n```\n<img src=x onerror=alert(1)>[/code]",
+                Format::Markdown
+            ),
+            "````\na\n```\n<img src=x onerror=alert(1)>\n````\n"
+        );
+        assert_eq!(parse_inline(r#""top":[#top]"#), vec![link("#top", "top")]);
+    }
+
+    #[test]
+    fn strips_control_characters() {
+        let dtext = "hi \x1b]0;pwned\x07[b]\x1b[2Jbold\u{9b}31m[/b]\r\n\tthere";
+
+        for &format in &[Format::Ansi, Format::Plain] {
+            assert_eq!(
+                render(dtext, format)
+                    .replace("\x1b[1m", "")
+                    .replace("\x1b[22m", ""),
+                "hi ]0;pwned[2Jbold31m\n\tthere\n"
+            );
+        }
+    }
+
+    #[test]
+    fn parses_blocks() {
+        assert_eq!(
+            parse("h2. Title\n* one\n** two\ntext[quote]quoted[/quote]"),
+            vec![
+                Block::Header(2, vec![text("Title")]),
+                Block::List(vec![(1, vec![text("one")]), (2, vec![text("two")])]),
+                Block::Paragraph(vec![text("text")]),
+                Block::Quote(vec![Block::Paragraph(vec![text("quoted")])]),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_code_without_extra_lines() {
+        let expected = vec![
+            Block::Paragraph(vec![text("before")]),
+            Block::Code("let x = 1;\n\nlet y = [b]2[/b];".into()),
+            Block::Paragraph(vec![text("after")]),
+        ];
+
+        assert_eq!(
+            parse("before\n[code]\nlet x = 1;\n\nlet y = [b]2[/b];\n[/code]\nafter"),
+            expected
+        );
+        assert_eq!(
+            parse("before[code]let x = 1;\n\nlet y = [b]2[/b];[/code]after"),
+            expected
+        );
+    }
+
+    #[test]
+    fn renders_formats() {
+        let dtext = "h1. A & B\n\n[b]bold[/b] \"link\":/posts/1\n\n* item";
+
+        assert_eq!(
+            render(dtext, Format::Plain),
+            "A & B\n=====\n\nbold link </posts/1>\n\n- item\n"
+        );
+        assert_eq!(
+            render(dtext, Format::Html),
+            concat!(
+                "<h1>A &amp; B</h1>\n",
+                "<p><strong>bold</strong> <a href=\"/posts/1\">link</a></p>\n",
+                "<ul>\n<li>item</li>\n</ul>\n",
+            )
+        );
+        assert_eq!(
+            render(dtext, Format::Markdown),
+            "# A & B\n\n**bold** [link](/posts/1)\n\n- item\n"
+        );
+    }
+
+    #[test]
+    fn renders_code_blocks() {
+        assert_eq!(
+            render("[code]a\n  b[/code]", Format::Markdown),
+            "```\na\n  b\n```\n"
+        );
+        assert_eq!(
+            render("[code]a\n  b[/code]", Format::Plain),
+            "    a\n      b\n"
+        );
+    }
+}
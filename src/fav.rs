@@ -0,0 +1,203 @@
+use crate::{
+    common::{
+        format_file_name, pipeline_args, rating_allowed, report_errors, save_post_to,
+        send_authenticated, user_agent, valid_parse, Credentials, Error, Result, CLIENT,
+    },
+    normal,
+};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use futures::{pin_mut, StreamExt};
+use rs621::client::Client;
+use std::{fs, path::Path};
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    let user_arg = Arg::with_name("user")
+        .index(1)
+        .help("Name of the user [default: the user given with --login]");
+    let ids_arg = Arg::with_name("ids")
+        .index(1)
+        .required(true)
+        .multiple(true)
+        .validator(|v| valid_parse::<u64>(&v, "Must be a positive integer."))
+        .help("IDs of the posts");
+
+    SubCommand::with_name("fav")
+        .about("Favorites related commands")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            SubCommand::with_name("list")
+                .about("Search the favorites of a user")
+                .arg(user_arg.clone())
+                .arg(
+                    Arg::with_name("limit")
+                        .short("l")
+                        .long("limit")
+                        .takes_value(true)
+                        .default_value("1")
+                        .validator(|v| valid_parse::<u64>(&v, "Must be a positive integer."))
+                        .help("Maximum search result count"),
+                )
+                .args(&pipeline_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("add")
+                .about("Add posts to your favorites")
+                .arg(ids_arg.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("remove")
+                .about("Remove posts from your favorites")
+                .arg(ids_arg),
+        )
+        .subcommand(
+            SubCommand::with_name("download")
+                .about("Download the favorites of a user that haven't been downloaded yet")
+                .arg(user_arg)
+                .arg(
+                    Arg::with_name("dir")
+                        .short("d")
+                        .long("dir")
+                        .takes_value(true)
+                        .default_value(".")
+                        .help("The folder to download favorites to"),
+                )
+                .arg(
+                    Arg::with_name("template")
+                        .short("t")
+                        .long("template")
+                        .takes_value(true)
+                        .default_value("{id}.{ext}")
+                        .help("File name template using {id}, {md5}, {ext}, {rating} and {artist}"),
+                )
+                .arg(
+                    Arg::with_name("full")
+                        .long("full")
+                        .help("Don't stop at the first favorite already downloaded"),
+                ),
+        )
+}
+
+// the user given as argument, or the authenticated one
+fn user(matches: &ArgMatches<'_>) -> Result<String> {
+    match matches.value_of("user") {
+        Some(user) => Ok(user.into()),
+        None => Ok(Credentials::from_matches(matches)?.login),
+    }
+}
+
+/// Adds a post to the favorites of the authenticated user.
+pub async fn add(url: &str, credentials: &Credentials, post_id: u64) -> Result<()> {
+    send_authenticated(
+        CLIENT
+            .post(format!("{}/favorites.json", url))
+            .form(&[("post_id", post_id)]),
+        credentials,
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Removes a post from the favorites of the authenticated user.
+pub async fn remove(url: &str, credentials: &Credentials, post_id: u64) -> Result<()> {
+    send_authenticated(
+        CLIENT.delete(format!("{}/favorites/{}.json", url, post_id)),
+        credentials,
+    )
+    .await?;
+
+    Ok(())
+}
+
+// get621 fav add|remove ...
+async fn edit(url: &str, matches: &ArgMatches<'_>, adding: bool) -> Result<()> {
+    let credentials = Credentials::from_matches(matches)?;
+
+    for id in matches.values_of("ids").unwrap() {
+        let id = id.parse().unwrap();
+
+        let result = if adding {
+            add(url, &credentials, id).await
+        } else {
+            remove(url, &credentials, id).await
+        };
+
+        match result {
+            Ok(()) if adding => println!("Added #{} to favorites", id),
+            Ok(()) => println!("Removed #{} from favorites", id),
+            Err(e) => eprintln!("#{}: {}", id, e),
+        }
+    }
+
+    Ok(())
+}
+
+// get621 fav download ...
+async fn download(url: &str, matches: &ArgMatches<'_>) -> Result<()> {
+    let user = user(matches)?;
+    let dir = Path::new(matches.value_of("dir").unwrap());
+    let template = matches.value_of("template").unwrap();
+    let flag_full = matches.is_present("full");
+
+    let client = Client::new(url, user_agent())?;
+    let tags = [format!("fav:{}", user)];
+    let tags = tags.iter().map(String::as_str).collect::<Vec<_>>();
+
+    // favorites are searched from the most recently added one
+    let posts = report_errors(
+        client
+            .post_search(&tags[..])
+            .map(|res| res.map_err(Error::from)),
+    );
+    pin_mut!(posts);
+
+    let mut saved = 0;
+
+    while let Some(post) = posts.next().await {
+        if !rating_allowed(&post.rating) || post.file.url.is_none() {
+            continue;
+        }
+
+        let path = dir.join(format_file_name(template, &post));
+
+        if path.exists() {
+            if flag_full {
+                continue;
+            } else {
+                break;
+            }
+        }
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        match save_post_to(&post, &path).await {
+            Ok(()) => {
+                println!("{}", path.display());
+                saved += 1;
+            }
+            Err(e) => eprintln!("Error when saving #{}: {}", post.id, e),
+        }
+    }
+
+    println!("Downloaded {} new favorites of {}", saved, user);
+
+    Ok(())
+}
+
+// get621 fav ...
+pub async fn run(url: &str, matches: &ArgMatches<'_>) -> Result<()> {
+    match matches.subcommand() {
+        ("list", Some(sub_matches)) => {
+            let client = Client::new(url, user_agent())?;
+            let tags = vec![format!("fav:{}", user(sub_matches)?)];
+
+            normal::search(url, &client, tags, sub_matches).await
+        }
+        ("add", Some(sub_matches)) => edit(url, sub_matches, true).await,
+        ("remove", Some(sub_matches)) => edit(url, sub_matches, false).await,
+        ("download", Some(sub_matches)) => download(url, sub_matches).await,
+        _ => unreachable!(),
+    }
+}
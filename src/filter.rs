@@ -0,0 +1,303 @@
+use crate::common::file_extension;
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use clap::{Arg, ArgMatches};
+use rs621::post::Post;
+use std::str::FromStr;
+
+/// An inclusive range of values, with optional bounds.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Range<T> {
+    pub min: Option<T>,
+    pub max: Option<T>,
+}
+
+impl<T> Default for Range<T> {
+    fn default() -> Self {
+        Range {
+            min: None,
+            max: None,
+        }
+    }
+}
+
+impl<T> Range<T> {
+    pub fn is_unbounded(&self) -> bool {
+        self.min.is_none() && self.max.is_none()
+    }
+}
+
+impl<T: PartialOrd> Range<T> {
+    pub fn contains(&self, value: T) -> bool {
+        self.min.iter().all(|min| &value >= min) && self.max.iter().all(|max| &value <= max)
+    }
+}
+
+impl<T: FromStr + Clone> FromStr for Range<T> {
+    type Err = T::Err;
+
+    // "min..max", "min..", "..max" or "value"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        fn bound<T: FromStr>(s: &str) -> Result<Option<T>, T::Err> {
+            if s.is_empty() {
+                Ok(None)
+            } else {
+                s.parse().map(Some)
+            }
+        }
+
+        if let Some(i) = s.find("..") {
+            Ok(Range {
+                min: bound(&s[..i])?,
+                max: bound(&s[i + 2..])?,
+            })
+        } else {
+            let value = s.parse::<T>()?;
+
+            Ok(Range {
+                min: Some(value.clone()),
+                max: Some(value),
+            })
+        }
+    }
+}
+
+/// An aspect ratio, given as a number or as "width:height".
+#[derive(Debug, Copy, Clone, PartialEq, PartialOrd)]
+pub struct AspectRatio(pub f64);
+
+impl FromStr for AspectRatio {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("Invalid aspect ratio: {}", s);
+
+        let ratio = match s.find(':') {
+            Some(i) => {
+                let width = s[..i].parse::<f64>().map_err(|_| err())?;
+                let height = s[i + 1..].parse::<f64>().map_err(|_| err())?;
+
+                width / height
+            }
+            None => s.parse().map_err(|_| err())?,
+        };
+
+        if ratio.is_finite() && ratio > 0.0 {
+            Ok(AspectRatio(ratio))
+        } else {
+            Err(err())
+        }
+    }
+}
+
+// parses a size in bytes with an optional K, M or G suffix
+fn parse_size(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let (number, unit) = match s.char_indices().last()? {
+        (i, 'k') | (i, 'K') => (&s[..i], 1 << 10),
+        (i, 'm') | (i, 'M') => (&s[..i], 1 << 20),
+        (i, 'g') | (i, 'G') => (&s[..i], 1 << 30),
+        _ => (s, 1),
+    };
+
+    number
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|n| *n >= 0.0)
+        .map(|n| (n * unit as f64) as u64)
+}
+
+// parses a date ("YYYY-MM-DD", midnight UTC) or a RFC 3339 date and time into a timestamp
+fn parse_date(s: &str) -> Option<i64> {
+    if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        Some(
+            Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0)?)
+                .timestamp(),
+        )
+    } else {
+        DateTime::parse_from_rfc3339(s).ok().map(|d| d.timestamp())
+    }
+}
+
+fn validator<T>(
+    parse: fn(&str) -> Option<T>,
+    emsg: &'static str,
+) -> impl Fn(String) -> Result<(), String> {
+    move |v| parse(&v).map(|_| ()).ok_or_else(|| emsg.to_string())
+}
+
+pub fn filter_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("min_score")
+            .long("min-score")
+            .takes_value(true)
+            .allow_hyphen_values(true)
+            .validator(validator(|v| v.parse::<i64>().ok(), "Must be an integer."))
+            .help("Only keep posts with at least this score"),
+        Arg::with_name("min_favs")
+            .long("min-favs")
+            .takes_value(true)
+            .validator(validator(
+                |v| v.parse::<u64>().ok(),
+                "Must be a positive integer.",
+            ))
+            .help("Only keep posts with at least this many favorites"),
+        Arg::with_name("width")
+            .long("width")
+            .takes_value(true)
+            .validator(validator(
+                |v| v.parse::<Range<u64>>().ok(),
+                "Must be a range such as 800..1920, 800.. or ..1920.",
+            ))
+            .help("Only keep posts whose width is in the given range (e.g. 1920..)"),
+        Arg::with_name("height")
+            .long("height")
+            .takes_value(true)
+            .validator(validator(
+                |v| v.parse::<Range<u64>>().ok(),
+                "Must be a range such as 600..1080, 600.. or ..1080.",
+            ))
+            .help("Only keep posts whose height is in the given range (e.g. ..1080)"),
+        Arg::with_name("aspect")
+            .long("aspect")
+            .takes_value(true)
+            .validator(validator(
+                |v| v.parse::<Range<AspectRatio>>().ok(),
+                "Must be an aspect ratio (e.g. 16:9 or 1.5) or a range of them (e.g. 4:3..16:9).",
+            ))
+            .help("Only keep posts with the given aspect ratio (e.g. 16:9) or range (e.g. 1..)"),
+        Arg::with_name("ext")
+            .long("ext")
+            .takes_value(true)
+            .use_delimiter(true)
+            .possible_values(&["jpg", "jpeg", "png", "gif", "swf", "webm"])
+            .help("Only keep posts with one of the given file types (e.g. jpg,png)"),
+        Arg::with_name("max_size")
+            .long("max-size")
+            .takes_value(true)
+            .validator(validator(
+                parse_size,
+                "Must be a size in bytes, optionally followed by K, M or G.",
+            ))
+            .help("Only keep posts whose file is at most this big (e.g. 10M)"),
+        Arg::with_name("created_after")
+            .long("created-after")
+            .takes_value(true)
+            .validator(validator(
+                parse_date,
+                "Must be a date (YYYY-MM-DD) or a RFC 3339 date and time.",
+            ))
+            .help("Only keep posts created after the given date"),
+        Arg::with_name("created_before")
+            .long("created-before")
+            .takes_value(true)
+            .validator(validator(
+                parse_date,
+                "Must be a date (YYYY-MM-DD) or a RFC 3339 date and time.",
+            ))
+            .help("Only keep posts created before the given date"),
+        Arg::with_name("has_source")
+            .long("has-source")
+            .help("Only keep posts with at least one source"),
+    ]
+}
+
+/// Conditions on the attributes of posts, checked locally.
+#[derive(Debug, Default)]
+pub struct PostFilter {
+    pub min_score: Option<i64>,
+    pub min_favs: Option<u64>,
+    pub width: Range<u64>,
+    pub height: Range<u64>,
+    pub aspect: Range<AspectRatio>,
+    pub extensions: Option<Vec<&'static str>>,
+    pub max_size: Option<u64>,
+    pub created_after: Option<i64>,
+    pub created_before: Option<i64>,
+    pub has_source: bool,
+}
+
+impl From<&ArgMatches<'_>> for PostFilter {
+    fn from(matches: &ArgMatches) -> Self {
+        // a single ratio matches close enough ratios as dimensions are rounded
+        let aspect = matches.value_of("aspect").map_or_else(Range::default, |v| {
+            let range = v.parse::<Range<AspectRatio>>().unwrap();
+
+            if range.min == range.max {
+                Range {
+                    min: range.min.map(|AspectRatio(r)| AspectRatio(r * 0.99)),
+                    max: range.max.map(|AspectRatio(r)| AspectRatio(r * 1.01)),
+                }
+            } else {
+                range
+            }
+        });
+
+        PostFilter {
+            min_score: matches.value_of("min_score").map(|v| v.parse().unwrap()),
+            min_favs: matches.value_of("min_favs").map(|v| v.parse().unwrap()),
+            width: matches
+                .value_of("width")
+                .map_or_else(Range::default, |v| v.parse().unwrap()),
+            height: matches
+                .value_of("height")
+                .map_or_else(Range::default, |v| v.parse().unwrap()),
+            aspect,
+            extensions: matches.values_of("ext").map(|exts| {
+                exts.map(|ext| match ext {
+                    "jpg" | "jpeg" => "jpg",
+                    "png" => "png",
+                    "gif" => "gif",
+                    "swf" => "swf",
+                    _ => "webm",
+                })
+                .collect()
+            }),
+            max_size: matches.value_of("max_size").and_then(parse_size),
+            created_after: matches.value_of("created_after").and_then(parse_date),
+            created_before: matches.value_of("created_before").and_then(parse_date),
+            has_source: matches.is_present("has_source"),
+        }
+    }
+}
+
+impl PostFilter {
+    /// Whether the filter matches every post.
+    pub fn is_empty(&self) -> bool {
+        self.min_score.is_none()
+            && self.min_favs.is_none()
+            && self.width.is_unbounded()
+            && self.height.is_unbounded()
+            && self.aspect.is_unbounded()
+            && self.extensions.is_none()
+            && self.max_size.is_none()
+            && self.created_after.is_none()
+            && self.created_before.is_none()
+            && !self.has_source
+    }
+
+    pub fn matches(&self, post: &Post) -> bool {
+        let file = &post.file;
+        let created_at = post.created_at.timestamp();
+
+        self.min_score.iter().all(|&min| post.score.total >= min)
+            && self.min_favs.iter().all(|&min| post.fav_count >= min)
+            && self.width.contains(file.width)
+            && self.height.contains(file.height)
+            && (file.height == 0
+                || self
+                    .aspect
+                    .contains(AspectRatio(file.width as f64 / file.height as f64)))
+            && self
+                .extensions
+                .iter()
+                .all(|exts| exts.contains(&file_extension(&file.ext)))
+            && self.max_size.iter().all(|&max| file.size <= max)
+            && self.created_after.iter().all(|&after| created_at >= after)
+            && self
+                .created_before
+                .iter()
+                .all(|&before| created_at < before)
+            && (!self.has_source || !post.sources.is_empty())
+    }
+}
@@ -0,0 +1,203 @@
+use crate::{
+    common::{pipeline_args, process_posts, stream_posts, Error, Result},
+    identify,
+};
+use clap::{crate_version, App, Arg, ArgMatches, SubCommand};
+use futures::StreamExt;
+use lazy_static::lazy_static;
+use regex::Regex;
+use rs621::{client::Client, pool::PoolSearch};
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader},
+    str::FromStr,
+};
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("get")
+        .about("Get posts by ID, URL or MD5")
+        .arg(
+            Arg::with_name("posts")
+                .index(1)
+                .multiple(true)
+                .required_unless("input")
+                .help("Post IDs, post URLs, pool URLs or MD5s"),
+        )
+        .arg(
+            Arg::with_name("input")
+                .short("i")
+                .long("input")
+                .takes_value(true)
+                .help("Read posts from a file, one per line; \"-\" reads the standard input"),
+        )
+        .args(&pipeline_args())
+}
+
+/// Something designating one or several posts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PostRef {
+    Id(u64),
+    Pool(u64),
+    Md5(String),
+}
+
+impl FromStr for PostRef {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        lazy_static! {
+            static ref MD5_REGEX: Regex = Regex::new(r"^[0-9a-fA-F]{32}$").unwrap();
+            static ref URL_REGEX: Regex =
+                Regex::new(r"^https?://[^/]+/(posts|post/show|pools|pool/show)/(\d+)").unwrap();
+        }
+
+        let s = s.trim();
+
+        if let Ok(id) = s.parse() {
+            Ok(PostRef::Id(id))
+        } else if MD5_REGEX.is_match(s) {
+            Ok(PostRef::Md5(s.to_lowercase()))
+        } else if let Some(caps) = URL_REGEX.captures(s) {
+            let id = caps[2]
+                .parse()
+                .map_err(|_| Error::InvalidPostReference(s.into()))?;
+
+            Ok(match &caps[1] {
+                "pools" | "pool/show" => PostRef::Pool(id),
+                _ => PostRef::Id(id),
+            })
+        } else {
+            Err(Error::InvalidPostReference(s.into()))
+        }
+    }
+}
+
+// reads post references from a file or stdin, ignoring empty lines
+fn read_refs(input: &str) -> Result<Vec<PostRef>> {
+    let reader: Box<dyn BufRead> = if input == "-" {
+        Box::new(BufReader::new(io::stdin()))
+    } else {
+        Box::new(BufReader::new(File::open(input)?))
+    };
+
+    let mut refs = Vec::new();
+
+    for line in reader.lines() {
+        let line = line?;
+
+        if !line.trim().is_empty() {
+            refs.push(line.parse()?);
+        }
+    }
+
+    Ok(refs)
+}
+
+// turns post references into post IDs, keeping their order
+async fn resolve(client: &Client, refs: Vec<PostRef>) -> Result<Vec<u64>> {
+    let md5s = refs
+        .iter()
+        .filter_map(|r| match r {
+            PostRef::Md5(md5) => Some(md5.clone()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    let by_md5 = if md5s.is_empty() {
+        Default::default()
+    } else {
+        identify::lookup(client, &md5s).await?
+    };
+
+    let mut ids = Vec::with_capacity(refs.len());
+
+    for r in refs {
+        match r {
+            PostRef::Id(id) => ids.push(id),
+            PostRef::Pool(pool_id) => {
+                match client
+                    .pool_search(PoolSearch::new().id(vec![pool_id]))
+                    .next()
+                    .await
+                {
+                    Some(pool) => ids.extend(pool?.post_ids),
+                    None => eprintln!("Pool #{} not found", pool_id),
+                }
+            }
+            PostRef::Md5(md5) => match by_md5.get(&md5) {
+                Some(post) => ids.push(post.id),
+                None => eprintln!("No post found with MD5 {}", md5),
+            },
+        }
+    }
+
+    Ok(ids)
+}
+
+// get621 get ...
+pub async fn run(url: &str, matches: &ArgMatches<'_>) -> Result<()> {
+    let mut refs = matches
+        .values_of("posts")
+        .into_iter()
+        .flatten()
+        .map(str::parse)
+        .collect::<Result<Vec<PostRef>>>()?;
+
+    if let Some(input) = matches.value_of("input") {
+        refs.extend(read_refs(input)?);
+    }
+
+    // Create client
+    let client = Client::new(
+        url,
+        format!("get621/{} (by nasso on e621)", crate_version!()),
+    )?;
+
+    let ids = resolve(&client, refs).await?;
+
+    process_posts(url, &client, stream_posts(&client, &ids), matches).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_post_references() {
+        let md5 = "d41d8cd98f00b204e9800998ecf8427e";
+        let cases = [
+            ("12345", Some(PostRef::Id(12345))),
+            (" 42\n", Some(PostRef::Id(42))),
+            ("https://e621.net/posts/12345", Some(PostRef::Id(12345))),
+            (
+                "https://e621.net/posts/12345?q=fox",
+                Some(PostRef::Id(12345)),
+            ),
+            ("http://e926.net/post/show/678", Some(PostRef::Id(678))),
+            ("https://e621.net/pools/9", Some(PostRef::Pool(9))),
+            ("https://e621.net/pool/show/10", Some(PostRef::Pool(10))),
+            (md5, Some(PostRef::Md5(md5.into()))),
+            (
+                "D41D8CD98F00B204E9800998ECF8427E",
+                Some(PostRef::Md5(md5.into())),
+            ),
+            ("", None),
+            ("fox", None),
+            ("-5", None),
+            ("d41d8cd98f00b204e9800998ecf8427", None),
+            ("d41d8cd98f00b204e9800998ecf8427ef", None),
+            ("g41d8cd98f00b204e9800998ecf8427e", None),
+            ("https://e621.net/users/12345", None),
+            ("https://e621.net/posts/", None),
+            ("e621.net/posts/12345", None),
+        ];
+
+        for (input, expected) in cases.iter() {
+            match (input.parse::<PostRef>(), expected) {
+                (Ok(post_ref), Some(expected)) => assert_eq!(&post_ref, expected, "{:?}", input),
+                (Err(Error::InvalidPostReference(_)), None) => (),
+                (result, _) => panic!("unexpected result for {:?}: {:?}", input, result),
+            }
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use crate::common::{expand_paths, file_extension, Result, Sidecar};
+use clap::{crate_version, App, Arg, ArgMatches, SubCommand};
+use futures::{pin_mut, StreamExt};
+use rs621::{client::Client, post::Post};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+// maximum number of hashes looked up in a single search
+const MD5_BATCH_SIZE: usize = 100;
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("identify")
+        .about("Find the posts of local files by their MD5")
+        .arg(
+            Arg::with_name("source")
+                .index(1)
+                .required(true)
+                .multiple(true)
+                .allow_hyphen_values(true)
+                .help("Files or folders to identify; can be a glob pattern"),
+        )
+        .arg(
+            Arg::with_name("rename")
+                .short("r")
+                .long("rename")
+                .help("Rename identified files to <post_id>.<ext>"),
+        )
+        .arg(
+            Arg::with_name("sidecar")
+                .long("sidecar")
+                .help("Write the tags of identified files to <file>.json"),
+        )
+        .arg(
+            Arg::with_name("output_mode")
+                .short("o")
+                .long("output")
+                .takes_value(true)
+                .default_value("text")
+                .possible_values(&["text", "json"])
+                .help("Set output mode; one of: text, json"),
+        )
+}
+
+#[derive(Debug, Serialize)]
+struct Identification {
+    path: PathBuf,
+    md5: String,
+    post_id: Option<u64>,
+    deleted: bool,
+}
+
+// finds the posts having the given MD5s
+pub async fn lookup(client: &Client, hashes: &[String]) -> Result<HashMap<String, Post>> {
+    let mut posts = HashMap::new();
+
+    for (i, batch) in hashes.chunks(MD5_BATCH_SIZE).enumerate() {
+        // stay well below the API rate limit
+        if i > 0 {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+
+        let md5_tag = format!("md5:{}", batch.join(","));
+        let results = client.post_search(&["status:any", &md5_tag[..]][..]);
+        pin_mut!(results);
+
+        while let Some(post) = results.next().await {
+            let post = post?;
+            posts.insert(post.file.md5.clone(), post);
+        }
+    }
+
+    Ok(posts)
+}
+
+// renames `path` to "<post_id>.<ext>" in the same folder, unless it would overwrite something
+fn rename(path: &Path, post: &Post) -> Result<PathBuf> {
+    let dest = path.with_file_name(format!("{}.{}", post.id, file_extension(&post.file.ext)));
+
+    if dest != path && !dest.exists() {
+        fs::rename(path, &dest)?;
+        Ok(dest)
+    } else {
+        Ok(path.to_owned())
+    }
+}
+
+// get621 identify ...
+pub async fn run(url: &str, matches: &ArgMatches<'_>) -> Result<()> {
+    let arg_source = matches.values_of("source").unwrap().collect::<Vec<_>>();
+    let flag_rename = matches.is_present("rename");
+    let flag_sidecar = matches.is_present("sidecar");
+    let json = matches.value_of("output_mode") == Some("json");
+
+    // Create client
+    let client = Client::new(
+        url,
+        format!("get621/{} (by nasso on e621)", crate_version!()),
+    )?;
+
+    let mut files = Vec::new();
+
+    for path in expand_paths(&arg_source)? {
+        match fs::read(&path) {
+            Ok(bytes) => files.push((path, format!("{:x}", md5::compute(&bytes)))),
+            Err(e) => eprintln!("Skipping {}: {}", path.display(), e),
+        }
+    }
+
+    let mut hashes = files.iter().map(|(_, md5)| md5.clone()).collect::<Vec<_>>();
+    hashes.sort();
+    hashes.dedup();
+
+    let posts = lookup(&client, &hashes).await?;
+
+    let mut identifications = Vec::with_capacity(files.len());
+
+    for (path, md5) in files {
+        let post = posts.get(&md5);
+        let mut path = path;
+
+        if let Some(post) = post {
+            if flag_rename {
+                path = rename(&path, post)?;
+            }
+
+            if flag_sidecar {
+                Sidecar::from(post).write(&path)?;
+            }
+        }
+
+        let identification = Identification {
+            path,
+            md5,
+            post_id: post.map(|p| p.id),
+            deleted: matches!(post, Some(p) if p.flags.deleted),
+        };
+
+        if !json {
+            match identification.post_id {
+                Some(id) if identification.deleted => {
+                    println!("{}: #{} (deleted)", identification.path.display(), id)
+                }
+                Some(id) => println!("{}: #{}", identification.path.display(), id),
+                None => println!("{}: not found", identification.path.display()),
+            }
+        }
+
+        identifications.push(identification);
+    }
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&identifications)?);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,369 @@
+use crate::common::{expand_paths, Result};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use image::{imageops::FilterType, GenericImageView};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::{Path, PathBuf},
+};
+
+/// Name of the index file created in a folder by `get621 index build`.
+pub const INDEX_FILE_NAME: &str = "get621.index.json";
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("index")
+        .about("Local perceptual hash index related commands")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            SubCommand::with_name("build")
+                .about("Hash the posts saved by get621 in a folder")
+                .arg(
+                    Arg::with_name("dir")
+                        .index(1)
+                        .required(true)
+                        .help("The folder containing the saved posts"),
+                )
+                .arg(
+                    Arg::with_name("file")
+                        .short("f")
+                        .long("file")
+                        .takes_value(true)
+                        .help("Where to write the index [default: <dir>/get621.index.json]"),
+                ),
+        )
+}
+
+/// The perceptual hash algorithms stored in the index.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HashKind {
+    Average,
+    Difference,
+    Perceptual,
+}
+
+impl From<&str> for HashKind {
+    fn from(s: &str) -> Self {
+        match s {
+            "ahash" => HashKind::Average,
+            "dhash" => HashKind::Difference,
+            "phash" => HashKind::Perceptual,
+            _ => panic!("Invalid hash algorithm: {}", s),
+        }
+    }
+}
+
+/// The perceptual hashes of an image.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImageHashes {
+    pub ahash: u64,
+    pub dhash: u64,
+    pub phash: u64,
+}
+
+impl ImageHashes {
+    /// Decodes the image at `path` and computes all of its hashes.
+    pub fn compute(path: &Path) -> Result<Self> {
+        Ok(ImageHashes::of(&image::open(path)?))
+    }
+
+    pub fn of(img: &image::DynamicImage) -> Self {
+        ImageHashes {
+            ahash: ahash(img),
+            dhash: dhash(img),
+            phash: phash(img),
+        }
+    }
+
+    pub fn get(&self, kind: HashKind) -> u64 {
+        match kind {
+            HashKind::Average => self.ahash,
+            HashKind::Difference => self.dhash,
+            HashKind::Perceptual => self.phash,
+        }
+    }
+}
+
+/// An image of the local collection along with its perceptual hashes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexEntry {
+    pub id: u64,
+    pub path: PathBuf,
+    pub hashes: ImageHashes,
+}
+
+/// A perceptual hash index of posts saved by get621.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Index {
+    pub entries: Vec<IndexEntry>,
+}
+
+impl Index {
+    /// Hashes every image saved by get621 in the given folder.
+    ///
+    /// Files whose name doesn't contain a post ID or that can't be decoded are skipped.
+    pub fn build(dir: &Path) -> Result<Self> {
+        let mut entries = Vec::new();
+
+        for path in expand_paths(&[glob::Pattern::escape(&dir.to_string_lossy())])? {
+            let id = match post_id_from_path(&path) {
+                Some(id) => id,
+                None => continue,
+            };
+
+            match ImageHashes::compute(&path) {
+                Ok(hashes) => entries.push(IndexEntry { id, path, hashes }),
+                Err(e) => eprintln!("Skipping {}: {}", path.display(), e),
+            }
+        }
+
+        Ok(Index { entries })
+    }
+
+    /// Reads an index previously written with [`Index::save`].
+    pub fn load(path: &Path) -> Result<Self> {
+        Ok(serde_json::from_reader(BufReader::new(File::open(path)?))?)
+    }
+
+    /// Loads the index at `path`.
+    ///
+    /// If `path` is a folder, its index file is loaded if it has one, otherwise the folder is
+    /// indexed on the fly.
+    pub fn open(path: &Path) -> Result<Self> {
+        if path.is_dir() {
+            let file = path.join(INDEX_FILE_NAME);
+
+            if file.is_file() {
+                Index::load(&file)
+            } else {
+                Index::build(path)
+            }
+        } else {
+            Index::load(path)
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        serde_json::to_writer(BufWriter::new(File::create(path)?), self)?;
+
+        Ok(())
+    }
+}
+
+// parses the ID out of a file name given by `save_post`: "<id>.<ext>" or "<pool>-<i>_<id>.<ext>"
+pub fn post_id_from_path(path: &Path) -> Option<u64> {
+    lazy_static! {
+        static ref SAVED_POST_REGEX: Regex = Regex::new(r"^(?:\d+-\d+_)?(\d+)$").unwrap();
+    }
+
+    let stem = path.file_stem()?.to_str()?;
+
+    SAVED_POST_REGEX
+        .captures(stem)
+        .and_then(|caps| caps[1].parse().ok())
+}
+
+// downscales an image to a grayscale matrix of the given size
+fn luma_matrix(img: &image::DynamicImage, width: u32, height: u32) -> Vec<Vec<f64>> {
+    let img = img
+        .resize_exact(width, height, FilterType::Triangle)
+        .grayscale();
+
+    (0..height)
+        .map(|y| {
+            (0..width)
+                .map(|x| f64::from(img.get_pixel(x, y)[0]))
+                .collect()
+        })
+        .collect()
+}
+
+// packs an iterator of up to 64 bits into an integer, first bit being the most significant one
+fn pack_bits(bits: impl Iterator<Item = bool>) -> u64 {
+    bits.fold(0, |hash, bit| (hash << 1) | u64::from(bit))
+}
+
+/// Computes the average hash (aHash) of an image.
+pub fn ahash(img: &image::DynamicImage) -> u64 {
+    let pixels = luma_matrix(img, 8, 8).concat();
+    let mean = pixels.iter().sum::<f64>() / pixels.len() as f64;
+
+    pack_bits(pixels.into_iter().map(|p| p > mean))
+}
+
+/// Computes the difference hash (dHash) of an image.
+pub fn dhash(img: &image::DynamicImage) -> u64 {
+    let pixels = luma_matrix(img, 9, 8);
+
+    pack_bits(
+        pixels
+            .iter()
+            .flat_map(|row| row.windows(2).map(|pair| pair[0] < pair[1])),
+    )
+}
+
+/// Computes the DCT based perceptual hash (pHash) of an image.
+pub fn phash(img: &image::DynamicImage) -> u64 {
+    const SIZE: usize = 32;
+    const LOW: usize = 8;
+
+    let pixels = luma_matrix(img, SIZE as u32, SIZE as u32);
+
+    // only the low frequencies of the 2D DCT-II are needed
+    let cos_table = (0..LOW)
+        .map(|u| {
+            (0..SIZE)
+                .map(|x| {
+                    ((2 * x + 1) as f64 * u as f64 * std::f64::consts::PI / (2 * SIZE) as f64).cos()
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    let mut coefficients = Vec::with_capacity(LOW * LOW);
+
+    for cos_v in cos_table.iter() {
+        for cos_u in cos_table.iter() {
+            let mut sum = 0.0;
+
+            for (y, row) in pixels.iter().enumerate() {
+                for (x, pixel) in row.iter().enumerate() {
+                    sum += pixel * cos_u[x] * cos_v[y];
+                }
+            }
+
+            coefficients.push(sum);
+        }
+    }
+
+    // the DC coefficient is left out of the median as it would skew it
+    let mut sorted = coefficients[1..].to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let median = sorted[sorted.len() / 2];
+
+    pack_bits(coefficients.into_iter().map(|c| c > median))
+}
+
+/// Turns the Hamming distance between two 64-bit hashes into a similarity in percents.
+pub fn similarity(a: u64, b: u64) -> f64 {
+    100.0 * (1.0 - f64::from((a ^ b).count_ones()) / 64.0)
+}
+
+// get621 index build ...
+fn build(matches: &ArgMatches<'_>) -> Result<()> {
+    let dir = Path::new(matches.value_of("dir").unwrap());
+    let file = matches
+        .value_of("file")
+        .map_or_else(|| dir.join(INDEX_FILE_NAME), PathBuf::from);
+
+    let index = Index::build(dir)?;
+    index.save(&file)?;
+
+    println!(
+        "Indexed {} posts into {}",
+        index.entries.len(),
+        file.display()
+    );
+
+    Ok(())
+}
+
+// get621 index ...
+pub fn run(matches: &ArgMatches<'_>) -> Result<()> {
+    match matches.subcommand() {
+        ("build", Some(sub_matches)) => build(sub_matches),
+        _ => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, ImageBuffer, Luma};
+
+    // two blobs of light over a diagonal gradient
+    fn sample_image() -> DynamicImage {
+        DynamicImage::ImageLuma8(ImageBuffer::from_fn(128, 96, |x, y| {
+            let blob = |cx: f64, cy: f64, r: f64| {
+                let d = ((f64::from(x) - cx).powi(2) + (f64::from(y) - cy).powi(2)).sqrt();
+                if d < r {
+                    120.0 * (1.0 - d / r)
+                } else {
+                    0.0
+                }
+            };
+            let value = f64::from(x + y) / 2.0 + blob(30.0, 30.0, 25.0) + blob(90.0, 60.0, 20.0);
+
+            Luma([value.min(255.0) as u8])
+        }))
+    }
+
+    // the same image, a bit brighter, downscaled, and with a small mark in a corner
+    fn altered_image() -> DynamicImage {
+        let img = sample_image().resize_exact(100, 75, FilterType::Triangle);
+        let mut img = img.brighten(12).to_luma8();
+
+        for x in 96..100 {
+            for y in 0..3 {
+                img.put_pixel(x, y, Luma([255]));
+            }
+        }
+
+        DynamicImage::ImageLuma8(img)
+    }
+
+    // a coarse checkerboard, nothing like the sample
+    fn unrelated_image() -> DynamicImage {
+        DynamicImage::ImageLuma8(ImageBuffer::from_fn(128, 96, |x, y| {
+            Luma([if (x / 12 + y / 40) % 2 == 0 { 230 } else { 20 }])
+        }))
+    }
+
+    #[test]
+    fn hashes_similar_images_alike() {
+        let sample = ImageHashes::of(&sample_image());
+        let altered = ImageHashes::of(&altered_image());
+        let unrelated = ImageHashes::of(&unrelated_image());
+
+        for &kind in &[
+            HashKind::Average,
+            HashKind::Difference,
+            HashKind::Perceptual,
+        ] {
+            let (sample, altered, unrelated) =
+                (sample.get(kind), altered.get(kind), unrelated.get(kind));
+
+            assert_eq!(similarity(sample, sample), 100.0);
+            assert!(
+                similarity(sample, altered) >= 85.0,
+                "{:?}: {}",
+                kind,
+                similarity(sample, altered)
+            );
+            assert!(
+                similarity(sample, unrelated) < 75.0,
+                "{:?}: {}",
+                kind,
+                similarity(sample, unrelated)
+            );
+        }
+    }
+
+    #[test]
+    fn computes_similarity_from_hamming_distance() {
+        assert_eq!(similarity(0, 0), 100.0);
+        assert_eq!(similarity(0, u64::MAX), 0.0);
+        assert_eq!(similarity(0b1111, 0), 100.0 * 60.0 / 64.0);
+    }
+
+    #[test]
+    fn parses_post_ids_from_saved_files() {
+        assert_eq!(post_id_from_path(Path::new("123.png")), Some(123));
+        assert_eq!(post_id_from_path(Path::new("dir/45-2_123.png")), Some(123));
+        assert_eq!(post_id_from_path(Path::new("123.png.json")), None);
+        assert_eq!(post_id_from_path(Path::new("get621.index.json")), None);
+        assert_eq!(post_id_from_path(Path::new("fox.png")), None);
+    }
+}
@@ -1,19 +1,70 @@
+mod artist;
+mod comments;
 mod common;
+mod dedupe;
+mod dtext;
+mod fav;
+mod filter;
+mod get;
+mod identify;
+mod index;
 mod normal;
+mod notes;
 mod pool;
+mod query;
 mod reverse;
+mod set;
+mod tags;
+mod upload;
+mod user;
+mod vote;
+mod wiki;
 
-use clap::{crate_version, App, ArgMatches};
+use clap::{crate_version, App, Arg, ArgMatches};
 
 // runs the program
 async fn run(matches: &ArgMatches<'_>) -> common::Result<()> {
+    common::init_max_rating(matches)?;
+
     match matches.subcommand() {
+        ("artist", Some(sub_matches)) => {
+            artist::run(matches.value_of("url").unwrap(), sub_matches).await
+        }
+        ("comments", Some(sub_matches)) => {
+            comments::run(matches.value_of("url").unwrap(), sub_matches).await
+        }
+        ("dedupe", Some(sub_matches)) => dedupe::run(sub_matches),
+        ("fav", Some(sub_matches)) => fav::run(matches.value_of("url").unwrap(), sub_matches).await,
+        ("get", Some(sub_matches)) => get::run(matches.value_of("url").unwrap(), sub_matches).await,
+        ("identify", Some(sub_matches)) => {
+            identify::run(matches.value_of("url").unwrap(), sub_matches).await
+        }
+        ("index", Some(sub_matches)) => index::run(sub_matches),
+        ("notes", Some(sub_matches)) => {
+            notes::run(matches.value_of("url").unwrap(), sub_matches).await
+        }
         ("pool", Some(sub_matches)) => {
             pool::run(matches.value_of("url").unwrap(), sub_matches).await
         }
         ("reverse", Some(sub_matches)) => {
             reverse::run(matches.value_of("url").unwrap(), sub_matches).await
         }
+        ("set", Some(sub_matches)) => set::run(matches.value_of("url").unwrap(), sub_matches).await,
+        ("tags", Some(sub_matches)) => {
+            tags::run(matches.value_of("url").unwrap(), sub_matches).await
+        }
+        ("upload", Some(sub_matches)) => {
+            upload::run(matches.value_of("url").unwrap(), sub_matches).await
+        }
+        ("user", Some(sub_matches)) => {
+            user::run(matches.value_of("url").unwrap(), sub_matches).await
+        }
+        ("vote", Some(sub_matches)) => {
+            vote::run(matches.value_of("url").unwrap(), sub_matches).await
+        }
+        ("wiki", Some(sub_matches)) => {
+            wiki::run(matches.value_of("url").unwrap(), sub_matches).await
+        }
         _ => normal::run(matches).await,
     }
 }
@@ -22,13 +73,52 @@ async fn run(matches: &ArgMatches<'_>) -> common::Result<()> {
 async fn main() {
     // CLI Arguments parsing
     let matches = App::new("get621")
-        .version(&crate_version!()[..])
+        .version(crate_version!())
         .author("nasso <nassomails ~ at ~ gmail {dot} com>")
         // default command
         .about("E621/926 command line tool")
         .args(&normal::args())
+        .arg(
+            Arg::with_name("max_rating")
+                .long("max-rating")
+                .takes_value(true)
+                .global(true)
+                .possible_values(&["safe", "questionable", "explicit"])
+                .help("Never output nor save posts with a more explicit rating"),
+        )
+        .arg(
+            Arg::with_name("login")
+                .long("login")
+                .takes_value(true)
+                .global(true)
+                .env("E621_LOGIN")
+                .help("Name of the user to authenticate as"),
+        )
+        .arg(
+            Arg::with_name("api_key")
+                .long("api-key")
+                .takes_value(true)
+                .global(true)
+                .env("E621_API_KEY")
+                .hide_env_values(true)
+                .help("API key to authenticate with"),
+        )
+        .subcommand(artist::subcommand())
+        .subcommand(comments::subcommand())
+        .subcommand(dedupe::subcommand())
+        .subcommand(fav::subcommand())
+        .subcommand(get::subcommand())
+        .subcommand(identify::subcommand())
+        .subcommand(index::subcommand())
+        .subcommand(notes::subcommand())
         .subcommand(pool::subcommand())
         .subcommand(reverse::subcommand())
+        .subcommand(set::subcommand())
+        .subcommand(tags::subcommand())
+        .subcommand(upload::subcommand())
+        .subcommand(user::subcommand())
+        .subcommand(vote::subcommand())
+        .subcommand(wiki::subcommand())
         .get_matches();
 
     ::std::process::exit(match run(&matches).await {
@@ -1,20 +1,43 @@
-use crate::common::{self, output_mode_check, output_posts, post_map, save_post, valid_parse};
+use crate::{
+    common::{
+        self, all_ratings_allowed, pipeline_args, process_posts, rating_allowed, valid_parse,
+        PostMapMode,
+    },
+    filter::PostFilter,
+    query::Query,
+    tags::{post_counts, resolve_aliases},
+};
 use clap::{crate_version, Arg, ArgMatches};
-use futures::{pin_mut, stream, StreamExt};
-use rs621::client::Client;
+use futures::{
+    future,
+    stream::{self, LocalBoxStream},
+    StreamExt,
+};
+use rs621::{
+    client::Client,
+    error::Result as Rs621Result,
+    post::{Post, SearchPage},
+};
+use std::collections::HashMap;
+
+const DEFAULT_MAX_API_TAGS: &str = "6";
+
+// number of posts per page of search results
+const API_PAGE_SIZE: u64 = 320;
+
+// highest page number the API accepts
+const MAX_API_PAGE: u64 = 750;
+
+// number of posts requested at once when iterating from the oldest post
+const ASCENDING_PAGE_SIZE: usize = 320;
 
 pub fn args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
-    vec![
+    let mut args = vec![
         Arg::with_name("url")
             .short("u")
             .long("url")
             .default_value("https://e926.net")
             .help("The URL of the server where requests should be made"),
-        Arg::with_name("children")
-            .short("c")
-            .long("children")
-            .conflicts_with("parents")
-            .help("Take the children of search results"),
         Arg::with_name("limit")
             .short("l")
             .long("limit")
@@ -22,66 +45,216 @@ pub fn args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
             .takes_value(true)
             .validator(|v| valid_parse::<u64>(&v, "Must be a positive integer."))
             .help("Maximum search result count"),
-        Arg::with_name("parents")
-            .short("p")
-            .long("parents")
-            .conflicts_with("children")
-            .help("Take the parent post of each search result, if any"),
-        Arg::with_name("save")
-            .short("s")
-            .long("save")
-            .help("Download every result to ./<post_id>.<ext>"),
-        Arg::with_name("output_mode")
-            .short("o")
-            .long("output")
+        Arg::with_name("page")
+            .long("page")
+            .takes_value(true)
+            .validator(|v| match v.parse::<u64>() {
+                Ok(page) if page > 0 => Ok(()),
+                _ => Err(String::from("Must be a strictly positive integer.")),
+            })
+            .help("Get the Nth page of --limit results"),
+        Arg::with_name("skip")
+            .long("skip")
+            .takes_value(true)
+            .validator(|v| valid_parse::<u64>(&v, "Must be a positive integer."))
+            .help("Skip the first N results"),
+        Arg::with_name("before_id")
+            .long("before-id")
             .takes_value(true)
-            .default_value("verbose")
-            .validator(output_mode_check)
-            .help("Set output mode; one of: id, raw, verbose"),
+            .validator(|v| valid_parse::<u64>(&v, "Must be a positive integer."))
+            .help("Only search posts older than the given post ID"),
+        Arg::with_name("after_id")
+            .long("after-id")
+            .takes_value(true)
+            .validator(|v| valid_parse::<u64>(&v, "Must be a positive integer."))
+            .help("Only search posts newer than the given post ID"),
+        Arg::with_name("oldest_first")
+            .long("oldest-first")
+            .help("Iterate from the oldest post to the newest one"),
+        Arg::with_name("max_api_tags")
+            .long("max-api-tags")
+            .takes_value(true)
+            .default_value(DEFAULT_MAX_API_TAGS)
+            .validator(|v| valid_parse::<usize>(&v, "Must be a positive integer."))
+            .help("Maximum number of tags sent to the API, the others are checked locally"),
+        Arg::with_name("resolve_aliases")
+            .long("resolve-aliases")
+            .help("Replace aliased tags with the tags they are an alias of before searching"),
         Arg::with_name("tags")
             .index(1)
             .multiple(true)
             .allow_hyphen_values(true)
             .help("Search tags"),
-    ]
+    ];
+
+    args.extend(pipeline_args());
+    args
+}
+
+// searches posts by ascending ID, newer than `after`
+fn search_ascending<'a>(
+    client: &'a Client,
+    tags: Vec<String>,
+    after: u64,
+) -> LocalBoxStream<'a, Rs621Result<Post>> {
+    stream::unfold(Some(after), move |cursor| {
+        let tags = tags.clone();
+
+        async move {
+            let after = cursor?;
+
+            let mut page_tags = tags;
+            page_tags.push(format!("id:>{}", after));
+            page_tags.push(String::from("order:id"));
+            let page_tags = page_tags.iter().map(String::as_str).collect::<Vec<_>>();
+
+            let mut page = Vec::new();
+            let mut last = after;
+            let mut results = client.post_search(&page_tags[..]).take(ASCENDING_PAGE_SIZE);
+
+            // each page is requested as a new search, only its first chunk can be trusted to
+            // be in ascending order
+            while let Some(result) = results.next().await {
+                match result {
+                    Ok(post) if post.id > last => {
+                        last = post.id;
+                        page.push(Ok(post));
+                    }
+                    Ok(_) => break,
+                    Err(e) => {
+                        page.push(Err(e));
+                        return Some((page, None));
+                    }
+                }
+            }
+
+            let cursor = if last > after { Some(last) } else { None };
+
+            Some((page, cursor))
+        }
+    })
+    .flat_map(stream::iter)
+    .boxed_local()
+}
+
+/// Searches posts with the given tags and runs the results through the pipeline.
+///
+/// The search options (`--limit`, `--page`, `--before-id`...) are read from `matches`, those
+/// that aren't arguments of the command having their default value.
+pub async fn search(
+    url: &str,
+    client: &Client,
+    mut tags: Vec<String>,
+    matches: &ArgMatches<'_>,
+) -> common::Result<()> {
+    let limit: u64 = matches.value_of("limit").map_or(1, |v| v.parse().unwrap());
+    let page: u64 = matches.value_of("page").map_or(1, |v| v.parse().unwrap());
+    let skip: u64 = matches.value_of("skip").map_or(0, |v| v.parse().unwrap());
+    let after_id: Option<u64> = matches.value_of("after_id").map(|v| v.parse().unwrap());
+    let before_id: Option<u64> = matches.value_of("before_id").map(|v| v.parse().unwrap());
+    let max_api_tags: usize = matches
+        .value_of("max_api_tags")
+        .unwrap_or(DEFAULT_MAX_API_TAGS)
+        .parse()
+        .unwrap();
+
+    if matches.is_present("resolve_aliases") {
+        let raw_tags = tags.iter().map(String::as_str).collect::<Vec<_>>();
+        tags = resolve_aliases(url, &raw_tags).await?;
+    }
+
+    let oldest_first = matches.is_present("oldest_first");
+
+    // tags added to the query to start from a post, or to iterate from the oldest one
+    let reserved = before_id.iter().count()
+        + if oldest_first {
+            2
+        } else {
+            after_id.iter().count()
+        };
+
+    // the tags sent to the API are chosen by how many posts they have
+    let ranked_tags = Query::ranked_tags(&tags, max_api_tags, reserved);
+    let post_counts = if ranked_tags.is_empty() {
+        HashMap::new()
+    } else {
+        post_counts(url, &ranked_tags).await?
+    };
+
+    let query = Query::parse(&tags, max_api_tags, reserved, &post_counts)?;
+    let mut tags = query.api_tags.clone();
+
+    if let Some(before_id) = before_id {
+        tags.push(format!("id:<{}", before_id));
+    }
+
+    // results are only filtered before being mapped to other posts when they are the output
+    let filter = match PostMapMode::from(matches) {
+        PostMapMode::None => PostFilter::from(matches),
+        _ => PostFilter::default(),
+    };
+
+    let mut skip = skip + (page - 1) * limit;
+
+    // Request
+    let post_stream = if oldest_first {
+        search_ascending(client, tags, after_id.unwrap_or(0))
+    } else {
+        if let Some(after_id) = after_id {
+            tags.push(format!("id:>{}", after_id));
+        }
+
+        let tags = tags.iter().map(String::as_str).collect::<Vec<_>>();
+
+        // without local filtering, the results before the wanted page don't need to be fetched,
+        // unless it's past the pages the API accepts
+        let first_page = skip / API_PAGE_SIZE + 1;
+
+        if query.filter.is_none()
+            && filter.is_empty()
+            && all_ratings_allowed()
+            && first_page <= MAX_API_PAGE
+        {
+            skip %= API_PAGE_SIZE;
+
+            client
+                .post_search_from_page(&tags[..], SearchPage::Page(first_page))
+                .boxed_local()
+        } else {
+            client.post_search(&tags[..]).boxed_local()
+        }
+    };
+
+    // keep requesting pages until enough posts match the local part of the query
+    let post_stream = post_stream
+        .filter(move |res| {
+            future::ready(match res {
+                Ok(post) => {
+                    rating_allowed(&post.rating) && query.matches(post) && filter.matches(post)
+                }
+                Err(_) => true,
+            })
+        })
+        .skip(skip as usize)
+        .take(limit as usize);
+
+    process_posts(url, client, post_stream, matches).await
 }
 
 // get621 ...
 pub async fn run(matches: &ArgMatches<'_>) -> common::Result<()> {
-    let limit: u64 = matches.value_of("limit").unwrap().parse().unwrap();
-    let flag_save = matches.is_present("save");
+    let url = matches.value_of("url").unwrap();
 
     // Create client
     let client = Client::new(
-        matches.value_of("url").unwrap(),
-        &format!("get621/{} (by nasso on e621)", crate_version!()),
+        url,
+        format!("get621/{} (by nasso on e621)", crate_version!()),
     )?;
 
     // search tags
     let tags = matches
         .values_of("tags")
-        .map_or_else(|| Vec::new(), |v| v.collect::<Vec<_>>());
-
-    // Request
-    let post_stream = client.post_search(&tags[..]).take(limit as usize);
-
-    // Get the posts
-    let posts = post_map(&client, matches.into(), post_stream).await?;
-    let post_stream = stream::iter(posts).then(|post| async move {
-        if flag_save {
-            if let Err(e) = save_post(&post, None).await {
-                eprintln!("Error when saving #{}: {}", post.id, e);
-            }
-        }
-
-        post
-    });
-    pin_mut!(post_stream);
-
-    // Do whatever the user asked us to do
-    let output_mode = matches.value_of("output_mode").unwrap().into();
-
-    output_posts(post_stream, output_mode).await?;
+        .map_or_else(Vec::new, |v| v.map(String::from).collect::<Vec<_>>());
 
-    Ok(())
+    search(url, &client, tags, matches).await
 }
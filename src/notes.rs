@@ -0,0 +1,248 @@
+use crate::common::{get_json_pages, valid_parse, Error, Result};
+use clap::{App, Arg, ArgMatches, SubCommand};
+use image::{DynamicImage, Rgba};
+use imageproc::{
+    drawing::{draw_filled_rect_mut, draw_hollow_rect_mut, draw_text_mut},
+    rect::Rect,
+};
+use lazy_static::lazy_static;
+use regex::Regex;
+use rusttype::{point, Font, Scale};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+// fonts tried when burning notes without --font
+const DEFAULT_FONTS: &[&str] = &[
+    "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/TTF/DejaVuSans.ttf",
+    "/usr/share/fonts/dejavu/DejaVuSans.ttf",
+    "/Library/Fonts/Arial.ttf",
+    "C:\\Windows\\Fonts\\arial.ttf",
+];
+
+// space between the border of a note and its text, in pixels
+const PADDING: u32 = 4;
+const MAX_FONT_SIZE: f32 = 18.0;
+const MIN_FONT_SIZE: f32 = 8.0;
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("notes")
+        .about("Show the notes of a post")
+        .arg(
+            Arg::with_name("id")
+                .index(1)
+                .required(true)
+                .validator(|v| valid_parse::<u64>(&v, "Must be a positive integer."))
+                .help("The ID of the post"),
+        )
+        .arg(
+            Arg::with_name("output_mode")
+                .short("o")
+                .long("output")
+                .takes_value(true)
+                .default_value("text")
+                .possible_values(&["text", "json"])
+                .help("Set output mode; one of: text, json"),
+        )
+}
+
+/// How notes are saved along with a post.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NotesMode {
+    /// Written to the JSON sidecar file.
+    Sidecar,
+    /// Drawn on a copy of the image.
+    Burn,
+}
+
+impl From<&str> for NotesMode {
+    fn from(s: &str) -> Self {
+        match s {
+            "sidecar" => NotesMode::Sidecar,
+            "burn" => NotesMode::Burn,
+            _ => panic!("Invalid notes mode: {}", s),
+        }
+    }
+}
+
+/// A note box, in pixels of the original image.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Note {
+    pub id: u64,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub body: String,
+    pub is_active: bool,
+}
+
+impl Note {
+    /// The body of the note as plain text.
+    ///
+    /// Note bodies are written in a subset of HTML.
+    pub fn text(&self) -> String {
+        lazy_static! {
+            static ref LINE_BREAK_REGEX: Regex = Regex::new(r"(?i)<br\s*/?>").unwrap();
+            static ref TAG_REGEX: Regex = Regex::new(r"<[^>]*>").unwrap();
+        }
+
+        let text = LINE_BREAK_REGEX.replace_all(&self.body, "\n");
+
+        TAG_REGEX
+            .replace_all(&text, "")
+            .replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&quot;", "\"")
+            .replace("&#39;", "'")
+            .replace("&amp;", "&")
+    }
+}
+
+/// Gets the active notes of a post.
+pub async fn get_notes(url: &str, post_id: u64) -> Result<Vec<Note>> {
+    let notes: Vec<Note> = get_json_pages(
+        url,
+        "/notes.json",
+        &[("search[post_id]", &post_id.to_string())],
+    )
+    .await?;
+
+    Ok(notes.into_iter().filter(|note| note.is_active).collect())
+}
+
+/// Loads the font used to burn notes, or the first system font found if `path` is `None`.
+pub fn load_font(path: Option<&Path>) -> Result<Font<'static>> {
+    let data = match path {
+        Some(path) => fs::read(path)?,
+        None => DEFAULT_FONTS
+            .iter()
+            .find_map(|path| fs::read(path).ok())
+            .ok_or(Error::FontNotFound)?,
+    };
+
+    Font::try_from_vec(data).ok_or(Error::FontNotFound)
+}
+
+fn text_width(font: &Font<'_>, scale: Scale, text: &str) -> u32 {
+    font.layout(text, scale, point(0.0, 0.0))
+        .filter_map(|glyph| glyph.pixel_bounding_box())
+        .map(|bounds| bounds.max.x.max(0) as u32)
+        .max()
+        .unwrap_or(0)
+}
+
+// splits text into lines fitting in the given width, breaking between words
+fn wrap(font: &Font<'_>, scale: Scale, text: &str, width: u32) -> Vec<String> {
+    let mut lines = Vec::new();
+
+    for paragraph in text.lines() {
+        let mut line = String::new();
+
+        for word in paragraph.split_whitespace() {
+            let candidate = if line.is_empty() {
+                word.to_string()
+            } else {
+                format!("{} {}", line, word)
+            };
+
+            if !line.is_empty() && text_width(font, scale, &candidate) > width {
+                lines.push(line);
+                line = word.to_string();
+            } else {
+                line = candidate;
+            }
+        }
+
+        lines.push(line);
+    }
+
+    lines
+}
+
+// finds the largest font size at which the text fits in a box
+fn fit(font: &Font<'_>, text: &str, width: u32, height: u32) -> (Scale, Vec<String>) {
+    let width = width.saturating_sub(2 * PADDING);
+    let height = height.saturating_sub(2 * PADDING);
+    let mut size = MAX_FONT_SIZE;
+
+    loop {
+        let scale = Scale::uniform(size);
+        let lines = wrap(font, scale, text, width);
+
+        if size <= MIN_FONT_SIZE || lines.len() as f32 * size <= height as f32 {
+            return (scale, lines);
+        }
+
+        size -= 1.0;
+    }
+}
+
+/// Draws notes on a copy of an image saved as `<name>.notes.<ext>`, returning its path.
+pub fn burn(file: &Path, notes: &[Note], font: &Font<'_>) -> Result<PathBuf> {
+    let mut img = image::open(file)?.to_rgba8();
+
+    for note in notes {
+        let rect =
+            Rect::at(note.x as i32, note.y as i32).of_size(note.width.max(1), note.height.max(1));
+
+        // same colors as on the site
+        draw_filled_rect_mut(&mut img, rect, Rgba([255, 255, 238, 255]));
+        draw_hollow_rect_mut(&mut img, rect, Rgba([0, 0, 0, 255]));
+
+        let (scale, lines) = fit(font, &note.text(), note.width, note.height);
+
+        for (i, line) in lines.iter().enumerate() {
+            draw_text_mut(
+                &mut img,
+                Rgba([0, 0, 0, 255]),
+                note.x + PADDING,
+                note.y + PADDING + (i as f32 * scale.y) as u32,
+                scale,
+                font,
+                line,
+            );
+        }
+    }
+
+    let extension = file
+        .extension()
+        .map_or_else(String::new, |ext| ext.to_string_lossy().into_owned());
+    let path = file.with_extension(format!("notes.{}", extension));
+
+    // not every format can be encoded with an alpha channel
+    DynamicImage::ImageRgba8(img).to_rgb8().save(&path)?;
+
+    Ok(path)
+}
+
+// get621 notes ...
+pub async fn run(url: &str, matches: &ArgMatches<'_>) -> Result<()> {
+    let post_id = matches.value_of("id").unwrap().parse().unwrap();
+    let notes = get_notes(url, post_id).await?;
+
+    if matches.value_of("output_mode") == Some("json") {
+        println!("{}", serde_json::to_string_pretty(&notes)?);
+        return Ok(());
+    }
+
+    for note in notes.iter() {
+        println!(
+            "{}, {} ({}x{}): {}",
+            note.x,
+            note.y,
+            note.width,
+            note.height,
+            note.text().replace('\n', " ")
+        );
+    }
+
+    if notes.is_empty() {
+        println!("No notes on #{}.", post_id);
+    }
+
+    Ok(())
+}
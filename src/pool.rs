@@ -1,33 +1,27 @@
-use crate::common::{
-    self, output_mode_check, output_posts, post_map, save_post, valid_parse, Error,
+use crate::{
+    common::{
+        self, output_mode_check, output_posts, post_map, post_map_args, rating_allowed,
+        report_errors, save_extras_args, save_post_with_extras, stream_posts, valid_parse, Error,
+        SaveExtras,
+    },
+    filter::{filter_args, PostFilter},
 };
 use clap::{crate_version, App, Arg, ArgMatches, SubCommand};
-use futures::{pin_mut, stream, StreamExt};
+use futures::{future, pin_mut, StreamExt};
 use rs621::{client::Client, pool::PoolSearch};
 
 pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
     SubCommand::with_name("pool")
         .about("Pool related commands")
-        .arg(
-            Arg::with_name("children")
-                .short("c")
-                .long("children")
-                .conflicts_with("parents")
-                .help("Take the children of search results"),
-        )
-        .arg(
-            Arg::with_name("parents")
-                .short("p")
-                .long("parents")
-                .conflicts_with("children")
-                .help("Take the parent post of each search result, if any"),
-        )
+        .args(&post_map_args())
+        .args(&filter_args())
         .arg(
             Arg::with_name("save")
                 .short("s")
                 .long("save")
                 .help("Download every result to ./<pool_id>-i_<post_id>.<ext>"),
         )
+        .args(&save_extras_args())
         .arg(
             Arg::with_name("output_mode")
                 .short("o")
@@ -35,7 +29,7 @@ pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
                 .takes_value(true)
                 .default_value("verbose")
                 .validator(output_mode_check)
-                .help("Set output mode; one of: id, raw, verbose"),
+                .help("Set output mode; one of: id, raw, verbose, tree, dot"),
         )
         .arg(
             Arg::with_name("id")
@@ -48,12 +42,11 @@ pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
 
 pub async fn run(url: &str, matches: &ArgMatches<'_>) -> common::Result<()> {
     let pool_id: u64 = matches.value_of("id").unwrap().parse().unwrap();
-    let flag_save = matches.is_present("save");
 
     // Create client
     let client = Client::new(
         url,
-        &format!("get621/{} (by nasso on e621)", crate_version!()),
+        format!("get621/{} (by nasso on e621)", crate_version!()),
     )?;
 
     // Get the posts
@@ -63,14 +56,38 @@ pub async fn run(url: &str, matches: &ArgMatches<'_>) -> common::Result<()> {
         .await
         .ok_or(Error::PoolNotFound)??
         .post_ids;
-    let posts = client.get_posts(&post_ids);
 
-    let posts = post_map(&client, matches.into(), posts).await?;
-    let post_stream = stream::iter(posts)
+    process_ordered_posts(url, &client, pool_id, &post_ids, matches).await
+}
+
+/// Maps, filters, saves and outputs the posts of a pool or set, in the order of `post_ids`.
+///
+/// Posts that can't be fetched (e.g. deleted ones) are skipped. Posts are saved to `<id>-<i>_<post_id>.<ext>`, `id` being the ID of the pool or set.
+pub async fn process_ordered_posts(
+    url: &str,
+    client: &Client,
+    id: u64,
+    post_ids: &[u64],
+    matches: &ArgMatches<'_>,
+) -> common::Result<()> {
+    let flag_save = matches.is_present("save");
+    let extras = SaveExtras::from(matches);
+    let extras = &extras;
+
+    let posts = stream_posts(client, post_ids);
+
+    let filter = PostFilter::from(matches);
+
+    let post_stream = report_errors(post_map(client, matches.into(), posts))
         .enumerate()
+        .filter(move |(_, post)| {
+            future::ready(rating_allowed(&post.rating) && filter.matches(post))
+        })
         .then(|(i, post)| async move {
             if flag_save {
-                if let Err(e) = save_post(&post, &format!("{}-{}_", pool_id, i)[..]).await {
+                let prefix = format!("{}-{}_", id, i);
+
+                if let Err(e) = save_post_with_extras(url, &post, &prefix[..], extras).await {
                     eprintln!("Error when saving #{}: {}", post.id, e);
                 }
             }
@@ -0,0 +1,366 @@
+use crate::common::{all_tags, Error, Result};
+use lazy_static::lazy_static;
+use regex::Regex;
+use rs621::post::Post;
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+};
+
+/// A search query evaluated locally, on the tags of a post.
+#[derive(Debug)]
+pub enum Expr {
+    Tag(String),
+    Wildcard(Regex),
+    Not(Box<Expr>),
+    All(Vec<Expr>),
+    Any(Vec<Expr>),
+}
+
+impl Expr {
+    pub fn matches(&self, tags: &HashSet<&str>) -> bool {
+        match self {
+            Expr::Tag(tag) => tags.contains(tag.as_str()),
+            Expr::Wildcard(regex) => tags.iter().any(|tag| regex.is_match(tag)),
+            Expr::Not(expr) => !expr.matches(tags),
+            Expr::All(exprs) => exprs.iter().all(|expr| expr.matches(tags)),
+            Expr::Any(exprs) => exprs.iter().any(|expr| expr.matches(tags)),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Prefix {
+    None,
+    Not,
+    Or,
+}
+
+#[derive(Debug)]
+enum Term {
+    Tag(String),
+    Group(Vec<Item>),
+}
+
+// a term of a group along with its prefix (e.g. "-tag" or "~( ... )")
+#[derive(Debug)]
+struct Item {
+    prefix: Prefix,
+    term: Term,
+}
+
+fn is_metatag(tag: &str) -> bool {
+    lazy_static! {
+        static ref METATAG_REGEX: Regex = Regex::new(r"^[a-z_]+:").unwrap();
+    }
+
+    METATAG_REGEX.is_match(tag)
+}
+
+// parses the items of a group, up to its closing parenthesis
+fn parse_group<'a>(tokens: &mut impl Iterator<Item = &'a str>, nested: bool) -> Result<Vec<Item>> {
+    let mut items = Vec::new();
+
+    while let Some(token) = tokens.next() {
+        if token == ")" {
+            return if nested {
+                Ok(items)
+            } else {
+                Err(Error::QueryError(String::from("unexpected ')'")))
+            };
+        }
+
+        let (prefix, rest) = match (token.strip_prefix('-'), token.strip_prefix('~')) {
+            (Some(rest), _) if !rest.is_empty() => (Prefix::Not, rest),
+            (_, Some(rest)) if !rest.is_empty() => (Prefix::Or, rest),
+            _ => (Prefix::None, token),
+        };
+
+        let term = if rest == "(" {
+            Term::Group(parse_group(tokens, true)?)
+        } else {
+            Term::Tag(rest.to_lowercase())
+        };
+
+        items.push(Item { prefix, term });
+    }
+
+    if nested {
+        Err(Error::QueryError(String::from("missing ')'")))
+    } else {
+        Ok(items)
+    }
+}
+
+fn tag_expr(tag: &str) -> Result<Expr> {
+    if is_metatag(tag) {
+        return Err(Error::QueryError(format!(
+            "\"{}\" can't be evaluated locally; metatags can't be in OR groups or nested groups \
+             when there are too many tags to send them all to the API",
+            tag
+        )));
+    }
+
+    Ok(if tag.contains('*') {
+        let pattern = format!("^{}$", regex::escape(tag).replace(r"\*", ".*"));
+        Expr::Wildcard(Regex::new(&pattern).unwrap())
+    } else {
+        Expr::Tag(tag.into())
+    })
+}
+
+fn split_tokens<S: AsRef<str>>(tags: &[S]) -> Vec<&str> {
+    tags.iter()
+        .flat_map(|tag| tag.as_ref().split_whitespace())
+        .collect()
+}
+
+// builds the local expression of a group: every item must match, and any of the "~" ones
+fn group_expr(items: Vec<Item>) -> Result<Expr> {
+    let mut all = Vec::new();
+    let mut any = Vec::new();
+
+    for item in items {
+        let expr = match item.term {
+            Term::Tag(tag) => tag_expr(&tag)?,
+            Term::Group(items) => group_expr(items)?,
+        };
+
+        match item.prefix {
+            Prefix::None => all.push(expr),
+            Prefix::Not => all.push(Expr::Not(Box::new(expr))),
+            Prefix::Or => any.push(expr),
+        }
+    }
+
+    if !any.is_empty() {
+        all.push(Expr::Any(any));
+    }
+
+    Ok(Expr::All(all))
+}
+
+/// A search query split between the tags sent to the API and a local filter.
+#[derive(Debug)]
+pub struct Query {
+    pub api_tags: Vec<String>,
+    pub filter: Option<Expr>,
+}
+
+impl Query {
+    /// Names of the tags that `parse` ranks by post count, none if the query fits in the limit.
+    pub fn ranked_tags<S: AsRef<str>>(
+        tags: &[S],
+        api_tag_limit: usize,
+        reserved: usize,
+    ) -> Vec<String> {
+        let tokens = split_tokens(tags);
+
+        if tokens.len() <= api_tag_limit.saturating_sub(reserved) {
+            return Vec::new();
+        }
+
+        // invalid queries are reported by `parse`
+        parse_group(&mut tokens.iter().copied(), false)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|item| match (item.prefix, item.term) {
+                (Prefix::None, Term::Tag(tag)) | (Prefix::Not, Term::Tag(tag))
+                    if !is_metatag(&tag) && !tag.contains('*') =>
+                {
+                    Some(tag)
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Parses search tags, only sending up to `api_tag_limit` of them to the API, minus
+    /// `reserved` slots for the tags added by the caller (e.g. `id:<N` to start from a post).
+    ///
+    /// Queries that fit in the limit are left entirely to the API. Otherwise, metatags are sent
+    /// first, followed by the required tags from the rarest to the most common and the exclusions
+    /// from the most common to the rarest, according to `post_counts`, and the rest is checked
+    /// locally. Tags of unknown post count come last, in the order they were given.
+    pub fn parse<S: AsRef<str>>(
+        tags: &[S],
+        api_tag_limit: usize,
+        reserved: usize,
+        post_counts: &HashMap<String, u64>,
+    ) -> Result<Self> {
+        let api_tag_limit = api_tag_limit.saturating_sub(reserved);
+        let tokens = split_tokens(tags);
+
+        if tokens.len() <= api_tag_limit {
+            return Ok(Query {
+                api_tags: tokens.into_iter().map(String::from).collect(),
+                filter: None,
+            });
+        }
+
+        let items = parse_group(&mut tokens.iter().copied(), false)?;
+
+        // metatags can only be evaluated by the API
+        let mut api_tags = Vec::new();
+        let mut positive = Vec::new();
+        let mut negative = Vec::new();
+        let mut local = Vec::new();
+
+        for item in items {
+            match (&item.prefix, &item.term) {
+                (Prefix::None, Term::Tag(tag)) if is_metatag(tag) => api_tags.push(tag.clone()),
+                (Prefix::Not, Term::Tag(tag)) if is_metatag(tag) => {
+                    api_tags.push(format!("-{}", tag))
+                }
+                (Prefix::None, Term::Tag(tag)) if !tag.contains('*') => positive.push(item),
+                (Prefix::Not, Term::Tag(tag)) if !tag.contains('*') => negative.push(item),
+                _ => local.push(item),
+            }
+        }
+
+        if api_tags.len() > api_tag_limit {
+            return Err(Error::QueryError(format!(
+                "too many metatags ({}, at most {} can be sent to the API)",
+                api_tags.len(),
+                api_tag_limit
+            )));
+        }
+
+        // required tags narrow the search down the most, then come exclusions, the most selective
+        // first
+        let post_count = |item: &Item| match item.term {
+            Term::Tag(ref tag) => post_counts.get(tag).copied(),
+            Term::Group(_) => None,
+        };
+
+        positive.sort_by_key(|item| post_count(item).unwrap_or(u64::MAX));
+        negative.sort_by_key(|item| Reverse(post_count(item).unwrap_or(0)));
+
+        for item in positive.into_iter().chain(negative) {
+            if api_tags.len() < api_tag_limit {
+                if let Term::Tag(ref tag) = item.term {
+                    api_tags.push(match item.prefix {
+                        Prefix::Not => format!("-{}", tag),
+                        _ => tag.clone(),
+                    });
+
+                    continue;
+                }
+            }
+
+            local.push(item);
+        }
+
+        Ok(Query {
+            api_tags,
+            filter: Some(group_expr(local)?),
+        })
+    }
+
+    /// Checks whether a post found with the API tags matches the rest of the query.
+    pub fn matches(&self, post: &Post) -> bool {
+        match self.filter {
+            Some(ref filter) => filter.matches(&all_tags(post).map(String::as_str).collect()),
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leaves_short_queries_to_the_api() {
+        let query = Query::parse(&["fox ~cat ~dog", "-wolf"], 6, 0, &HashMap::new()).unwrap();
+
+        assert_eq!(query.api_tags, vec!["fox", "~cat", "~dog", "-wolf"]);
+        assert!(query.filter.is_none());
+    }
+
+    #[test]
+    fn sends_metatags_then_required_tags_then_exclusions() {
+        let query = Query::parse(
+            &["-wolf ~cat ~dog fox order:score a* solo"],
+            4,
+            0,
+            &HashMap::new(),
+        )
+        .unwrap();
+
+        assert_eq!(query.api_tags, vec!["order:score", "fox", "solo", "-wolf"]);
+
+        let filter = query.filter.unwrap();
+        let matches = |post_tags: &[&str]| filter.matches(&post_tags.iter().copied().collect());
+
+        assert!(matches(&["fox", "solo", "cat", "anthro"]));
+        assert!(!matches(&["fox", "solo", "cat"]));
+        assert!(!matches(&["fox", "solo", "anthro"]));
+    }
+
+    #[test]
+    fn ranks_tags_by_post_count() {
+        let tags = ["order:score a -b c ~d e* -f g"];
+
+        assert_eq!(
+            Query::ranked_tags(&tags, 4, 0),
+            vec!["a", "b", "c", "f", "g"]
+        );
+        assert!(Query::ranked_tags(&tags, 8, 0).is_empty());
+
+        let post_counts = [("a", 5000), ("b", 10), ("c", 30), ("f", 900)]
+            .iter()
+            .map(|&(tag, count)| (String::from(tag), count))
+            .collect();
+        let query = Query::parse(&tags, 5, 0, &post_counts).unwrap();
+
+        assert_eq!(query.api_tags, vec!["order:score", "c", "a", "g", "-f"]);
+
+        let query = Query::parse(&tags, 4, 0, &HashMap::new()).unwrap();
+
+        assert_eq!(query.api_tags, vec!["order:score", "a", "c", "g"]);
+    }
+
+    #[test]
+    fn checks_extra_tags_locally() {
+        let query = Query::parse(&["a b c -d -e"], 3, 0, &HashMap::new()).unwrap();
+
+        assert_eq!(query.api_tags, vec!["a", "b", "c"]);
+
+        let filter = query.filter.unwrap();
+        assert!(filter.matches(&["a", "b", "c"].iter().copied().collect()));
+        assert!(!filter.matches(&["a", "b", "c", "e"].iter().copied().collect()));
+    }
+
+    #[test]
+    fn reserves_slots_for_extra_tags() {
+        let query = Query::parse(&["a b c d"], 4, 2, &HashMap::new()).unwrap();
+
+        assert_eq!(query.api_tags, vec!["a", "b"]);
+        assert!(query.filter.is_some());
+    }
+
+    #[test]
+    fn rejects_too_many_metatags() {
+        match Query::parse(
+            &["fox order:score rating:s -status:deleted id:>5"],
+            3,
+            0,
+            &HashMap::new(),
+        ) {
+            Err(Error::QueryError(message)) => assert!(message.contains("metatags")),
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn rejects_metatags_in_local_groups() {
+        assert!(Query::parse(&["a b c ~order:score ~d"], 2, 0, &HashMap::new()).is_err());
+    }
+
+    #[test]
+    fn rejects_unbalanced_groups() {
+        assert!(Query::parse(&["a b c ( d"], 2, 0, &HashMap::new()).is_err());
+        assert!(Query::parse(&["a b c ) d"], 2, 0, &HashMap::new()).is_err());
+    }
+}
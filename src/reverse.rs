@@ -1,19 +1,23 @@
-use crate::common::{
-    self, download, expand_paths, output_mode_check, output_posts, save_post, valid_parse, Error,
-    OutputMode, Result,
+use crate::{
+    common::{
+        self, all_ratings_allowed, download, expand_paths, fetch_posts, file_part,
+        output_mode_check, output_posts, rating_allowed, rating_name_allowed, save_post,
+        stream_posts, valid_parse, Error, OutputMode, Result,
+    },
+    filter::{filter_args, PostFilter},
+    index::{self, HashKind, ImageHashes, Index},
 };
 use clap::{crate_version, App, Arg, ArgMatches, SubCommand};
-use futures::{pin_mut, StreamExt};
-use lazy_static::lazy_static;
-use regex::Regex;
-use reqwest::{
-    self,
-    multipart::{self, Part},
+use futures::{
+    future::{self, LocalBoxFuture},
+    pin_mut, FutureExt, StreamExt,
 };
+use lazy_static::lazy_static;
+use reqwest::{self, multipart};
 use rs621::client::Client;
 use scraper::{Html, Selector};
 use serde::Deserialize;
-use std::{fs::File, io::Read, path::Path, time::Duration};
+use std::{fs::File, path::Path, time::Duration};
 
 // arguments of the subcommand
 pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
@@ -37,6 +41,43 @@ pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
                 .validator(|v| valid_parse::<f64>(&v, "Must be a floating point value."))
                 .help("Set the similarity threshold for matching posts (in percents)"),
         )
+        .arg(
+            Arg::with_name("backend")
+                .short("b")
+                .long("backend")
+                .takes_value(true)
+                .default_value("iqdb")
+                .possible_values(&["iqdb", "saucenao", "local"])
+                .help("Set the reverse search service to use"),
+        )
+        .arg(
+            Arg::with_name("saucenao_key")
+                .long("saucenao-key")
+                .takes_value(true)
+                .env("SAUCENAO_API_KEY")
+                .required_if("backend", "saucenao")
+                .help("The API key to use with the SauceNAO backend"),
+        )
+        .arg(
+            Arg::with_name("local")
+                .long("local")
+                .help("Search the local index only; same as --backend local"),
+        )
+        .arg(
+            Arg::with_name("index")
+                .long("index")
+                .takes_value(true)
+                .default_value(".")
+                .help("The index file or folder of saved posts to search with the local backend"),
+        )
+        .arg(
+            Arg::with_name("hash")
+                .long("hash")
+                .takes_value(true)
+                .default_value("dhash")
+                .possible_values(&["ahash", "dhash", "phash"])
+                .help("Set the perceptual hash algorithm used by the local backend"),
+        )
         .arg(
             Arg::with_name("save")
                 .short("s")
@@ -58,15 +99,54 @@ pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
                 .takes_value(true)
                 .default_value("verbose")
                 .validator(output_mode_check)
-                .help("Set output mode; one of: id, raw, verbose"),
+                .help("Set output mode; one of: id, raw, verbose, tree, dot"),
         )
+        .args(&filter_args())
+}
+
+/// A post matched by a reverse search backend.
+#[derive(Debug)]
+pub struct Candidate {
+    pub id: u64,
+    /// How similar the post is to the searched image, from 0 to 100.
+    pub similarity: f64,
+    pub file_url: Option<String>,
+    pub file_ext: Option<String>,
+    /// First letter of the rating of the post, if known.
+    pub rating: Option<String>,
+}
+
+/// A service able to find posts similar to a local image.
+pub trait ReverseBackend {
+    /// Returns the candidates matching the image at `path`, most similar first.
+    fn search<'a>(&'a self, path: &'a Path) -> LocalBoxFuture<'a, Result<Vec<Candidate>>>;
+}
+
+fn sort_candidates(candidates: &mut [Candidate]) {
+    candidates.sort_by(|a, b| {
+        b.similarity
+            .partial_cmp(&a.similarity)
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+}
+
+/// e621's own IQDB instance, queried through the `/iqdb_queries` page.
+pub struct Iqdb {
+    url: String,
+}
+
+impl Iqdb {
+    pub fn new(url: &str) -> Self {
+        Iqdb { url: url.into() }
+    }
 }
 
 #[derive(Deserialize)]
-struct ReverseSearchResult {
+struct IqdbPost {
     id: u64,
     file_ext: Option<String>,
     file_url: Option<String>,
+    rating: Option<String>,
 }
 
 async fn get_csrf_token(page_url: &str) -> Result<(String, String)> {
@@ -103,77 +183,250 @@ async fn get_csrf_token(page_url: &str) -> Result<(String, String)> {
     ))
 }
 
-// do the reverse search and return the results
-async fn reverse_search(
-    url: &str,
-    path: &Path,
-    min_similarity: f64,
-) -> Result<Vec<ReverseSearchResult>> {
-    lazy_static! {
-        static ref SELECT_RESULTS: Selector = Selector::parse(".post-preview").unwrap();
-        static ref POST_SIMILARITY_REGEX: Regex = Regex::new(r#"Similarity:?\s*(\d+)"#).unwrap();
+impl Iqdb {
+    async fn query(&self, path: &Path) -> Result<Vec<Candidate>> {
+        let (token, cookie) = get_csrf_token(&format!("{}/iqdb_queries", self.url)).await?;
+
+        let form = multipart::Form::new()
+            .text("authenticity_token", token)
+            .text("url", "")
+            .part("file", file_part(path)?);
+
+        tokio::time::sleep(Duration::from_secs(2)).await;
+
+        let json: serde_json::Value = common::CLIENT
+            .post(format!("{}/iqdb_queries.json", self.url))
+            .header(
+                "User-Agent",
+                &format!("get621/{} (by nasso on e621)", crate_version!()),
+            )
+            .header("Cookie", cookie)
+            .multipart(form)
+            .send()
+            .await?
+            .json()
+            .await?;
+
+        iqdb_candidates(json)
     }
+}
 
-    let (token, cookie) = get_csrf_token(&format!("{}/iqdb_queries", url)).await?;
-
-    let form = multipart::Form::new()
-        .text("authenticity_token", token)
-        .text("url", "")
-        .part("file", {
-            let file_name = path
-                .file_name()
-                .map(|filename| filename.to_string_lossy().into_owned());
-            let mime = mime_guess::from_path(path).first_or_octet_stream();
-
-            let mut file = File::open(path)?;
-            let mut bytes = Vec::new();
-            file.read_to_end(&mut bytes)?;
-
-            let field = Part::bytes(bytes).mime_str(mime.essence_str())?;
-
-            if let Some(file_name) = file_name {
-                field.file_name(file_name)
-            } else {
-                field
-            }
-        });
-
-    std::thread::sleep(Duration::from_secs(2));
-
-    let mut json: serde_json::Value = common::CLIENT
-        .post(format!("{}/iqdb_queries.json", url))
-        .header(
-            "User-Agent",
-            &format!("get621/{} (by nasso on e621)", crate_version!()),
-        )
-        .header("Cookie", cookie)
-        .multipart(form)
-        .send()
-        .await?
-        .json()
-        .await?;
-
+// reads the candidates out of a response of `/iqdb_queries.json`
+fn iqdb_candidates(mut json: serde_json::Value) -> Result<Vec<Candidate>> {
     let pretty_json = serde_json::to_string_pretty(&json)?;
 
-    let mut results = Vec::new();
+    let mut candidates = Vec::new();
 
     if json["posts"].as_array().map(Vec::is_empty) != Some(true) {
         for candidate in json
             .as_array_mut()
             .ok_or(Error::IqdbResponseParseError(pretty_json.clone()))?
-            .into_iter()
+            .iter_mut()
         {
             if let Some(similarity) = candidate["score"].as_f64() {
-                if similarity >= min_similarity {
-                    results.push(serde_json::from_value(candidate["post"]["posts"].take())?);
-                }
+                let post: IqdbPost = serde_json::from_value(candidate["post"]["posts"].take())?;
+
+                candidates.push(Candidate {
+                    id: post.id,
+                    similarity: similarity.clamp(0.0, 100.0),
+                    file_url: post.file_url,
+                    file_ext: post.file_ext,
+                    rating: post.rating,
+                });
             } else {
                 return Err(Error::IqdbResponseParseError(pretty_json));
             }
         }
     }
 
-    Ok(results)
+    sort_candidates(&mut candidates);
+
+    Ok(candidates)
+}
+
+impl ReverseBackend for Iqdb {
+    fn search<'a>(&'a self, path: &'a Path) -> LocalBoxFuture<'a, Result<Vec<Candidate>>> {
+        self.query(path).boxed_local()
+    }
+}
+
+/// The SauceNAO API, restricted to its e621 database.
+pub struct SauceNao {
+    api_key: String,
+    endpoint: String,
+}
+
+impl SauceNao {
+    // index of the e621 database on SauceNAO
+    const E621_DB: &'static str = "29";
+
+    pub fn new(api_key: &str) -> Self {
+        SauceNao::with_endpoint(api_key, "https://saucenao.com")
+    }
+
+    /// Creates a backend querying another SauceNAO-compatible server.
+    pub fn with_endpoint(api_key: &str, endpoint: &str) -> Self {
+        SauceNao {
+            api_key: api_key.into(),
+            endpoint: endpoint.trim_end_matches('/').into(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct SauceNaoResponse {
+    header: SauceNaoResponseHeader,
+    #[serde(default)]
+    results: Vec<SauceNaoResult>,
+}
+
+#[derive(Deserialize)]
+struct SauceNaoResponseHeader {
+    status: i64,
+    message: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SauceNaoResult {
+    header: SauceNaoResultHeader,
+    data: SauceNaoResultData,
+}
+
+#[derive(Deserialize)]
+struct SauceNaoResultHeader {
+    similarity: String,
+}
+
+#[derive(Deserialize)]
+struct SauceNaoResultData {
+    e621_id: Option<u64>,
+}
+
+impl SauceNao {
+    async fn query(&self, path: &Path) -> Result<Vec<Candidate>> {
+        let form = multipart::Form::new().part("file", file_part(path)?);
+
+        let response: SauceNaoResponse = common::CLIENT
+            .post(format!("{}/search.php", self.endpoint))
+            .query(&[
+                ("output_type", "2"),
+                ("db", SauceNao::E621_DB),
+                ("api_key", self.api_key.as_str()),
+            ])
+            .header(
+                "User-Agent",
+                &format!("get621/{} (by nasso on e621)", crate_version!()),
+            )
+            .multipart(form)
+            .send()
+            .await?
+            .json()
+            .await?;
+
+        saucenao_candidates(response)
+    }
+}
+
+// reads the candidates out of a response of SauceNAO
+fn saucenao_candidates(response: SauceNaoResponse) -> Result<Vec<Candidate>> {
+    // a negative status is a client-side error, a positive one a server-side error
+    if response.header.status != 0 {
+        let status = response.header.status;
+
+        return Err(Error::SauceNaoError(
+            response
+                .header
+                .message
+                .unwrap_or_else(|| format!("status {}", status)),
+        ));
+    }
+
+    let mut candidates = response
+        .results
+        .into_iter()
+        .filter_map(|result| {
+            Some(Candidate {
+                id: result.data.e621_id?,
+                similarity: result
+                    .header
+                    .similarity
+                    .parse::<f64>()
+                    .ok()?
+                    .clamp(0.0, 100.0),
+                file_url: None,
+                file_ext: None,
+                rating: None,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    sort_candidates(&mut candidates);
+
+    Ok(candidates)
+}
+
+impl ReverseBackend for SauceNao {
+    fn search<'a>(&'a self, path: &'a Path) -> LocalBoxFuture<'a, Result<Vec<Candidate>>> {
+        self.query(path).boxed_local()
+    }
+}
+
+/// A perceptual hash index of the images in a local folder.
+pub struct LocalIndex {
+    index: Index,
+    kind: HashKind,
+}
+
+impl LocalIndex {
+    pub fn new(index: Index, kind: HashKind) -> Self {
+        LocalIndex { index, kind }
+    }
+}
+
+impl ReverseBackend for LocalIndex {
+    fn search<'a>(&'a self, path: &'a Path) -> LocalBoxFuture<'a, Result<Vec<Candidate>>> {
+        async move {
+            let hash = ImageHashes::compute(path)?.get(self.kind);
+
+            let mut candidates = self
+                .index
+                .entries
+                .iter()
+                .map(|entry| Candidate {
+                    id: entry.id,
+                    similarity: index::similarity(hash, entry.hashes.get(self.kind)),
+                    file_url: None,
+                    file_ext: None,
+                    rating: None,
+                })
+                .collect::<Vec<_>>();
+
+            sort_candidates(&mut candidates);
+
+            Ok(candidates)
+        }
+        .boxed_local()
+    }
+}
+
+// creates the backend selected on the command line
+fn backend(url: &str, matches: &ArgMatches<'_>) -> Result<Box<dyn ReverseBackend>> {
+    let name = if matches.is_present("local") {
+        "local"
+    } else {
+        matches.value_of("backend").unwrap()
+    };
+
+    let backend: Box<dyn ReverseBackend> = match name {
+        "saucenao" => Box::new(SauceNao::new(matches.value_of("saucenao_key").unwrap())),
+        "local" => Box::new(LocalIndex::new(
+            Index::open(Path::new(matches.value_of("index").unwrap()))?,
+            matches.value_of("hash").unwrap().into(),
+        )),
+        _ => Box::new(Iqdb::new(url)),
+    };
+
+    Ok(backend)
 }
 
 // get621 reverse ...
@@ -182,21 +435,19 @@ pub async fn run(url: &str, matches: &ArgMatches<'_>) -> Result<()> {
     let arg_similarity = matches.value_of("similarity").unwrap().parse().unwrap();
     let arg_outputmode = matches.value_of("output_mode").unwrap();
     let flag_save = matches.is_present("save");
+    let filter = PostFilter::from(matches);
 
-    let vb = match arg_outputmode.into() {
-        OutputMode::Verbose => true,
-        _ => false,
-    };
+    let vb = matches!(arg_outputmode.into(), OutputMode::Verbose);
+
+    let flag_direct_save = matches.is_present("direct_save");
 
     // Create client
-    let client = if matches.is_present("direct_save") {
-        None
-    } else {
-        Some(Client::new(
-            url,
-            &format!("get621/{} (by nasso on e621)", crate_version!()),
-        )?)
-    };
+    let client = Client::new(
+        url,
+        format!("get621/{} (by nasso on e621)", crate_version!()),
+    )?;
+
+    let backend = backend(url, matches)?;
 
     // macro for verbose output -> println!
     macro_rules! verbose_println {
@@ -212,16 +463,19 @@ pub async fn run(url: &str, matches: &ArgMatches<'_>) -> Result<()> {
         verbose_println!("================================");
 
         // do the reverse search
-        let results = reverse_search(url, &path, arg_similarity).await?;
+        let results = backend
+            .search(&path)
+            .await?
+            .into_iter()
+            .filter(|candidate| candidate.similarity >= arg_similarity)
+            .collect::<Vec<_>>();
 
         if results.is_empty() {
             verbose_println!("No result.");
-        } else if let Some(ref client) = client {
+        } else if !flag_direct_save {
             // just get post information
             let post_ids = results.into_iter().map(|r| r.id).collect::<Vec<_>>();
-            let posts = client
-                .get_posts(&post_ids)
-                .map(|r| r.map_err(Error::from))
+            let posts = stream_posts(&client, &post_ids)
                 .filter_map(|res| async move {
                     match res {
                         Ok(post) => Some(post),
@@ -231,6 +485,7 @@ pub async fn run(url: &str, matches: &ArgMatches<'_>) -> Result<()> {
                         }
                     }
                 })
+                .filter(|post| future::ready(rating_allowed(&post.rating) && filter.matches(post)))
                 .then(|post| async move {
                     if flag_save {
                         if let Err(e) = save_post(&post, None).await {
@@ -245,10 +500,26 @@ pub async fn run(url: &str, matches: &ArgMatches<'_>) -> Result<()> {
             // output all the posts as usual
             output_posts(posts, arg_outputmode.into()).await?;
         } else {
-            // no client = directly download the image
+            // posts whose file isn't known are looked up before being saved
+            let mut missing = Vec::new();
+
             for result in results.into_iter() {
-                if let (id, Some(file_url), Some(file_ext)) =
-                    (result.id, result.file_url, result.file_ext)
+                // only a rating given by the backend can rule a post out here, an unknown rating
+                // is checked once the post is looked up
+                if result.rating.is_some() && !rating_name_allowed(result.rating.as_deref()) {
+                    verbose_println!(
+                        "Skipping #{}: its rating is above the maximum rating.",
+                        result.id
+                    );
+                    continue;
+                }
+
+                // the filter needs post information, so filtered candidates are always looked up
+                let checked =
+                    (result.rating.is_some() || all_ratings_allowed()) && filter.is_empty();
+
+                if let (id, Some(file_url), Some(file_ext), true) =
+                    (result.id, result.file_url, result.file_ext, checked)
                 {
                     verbose_println!("Downloading {}...", file_url);
 
@@ -257,10 +528,19 @@ pub async fn run(url: &str, matches: &ArgMatches<'_>) -> Result<()> {
 
                     download(file_url, &mut dest).await?;
                 } else {
-                    verbose_println!(
-                        "Found #{} but the file URL is missing. The post was probably deleted.",
-                        result.id
-                    )
+                    missing.push(result.id);
+                }
+            }
+
+            for post in fetch_posts(&client, &missing)
+                .await?
+                .into_iter()
+                .filter(|post| filter.matches(post))
+            {
+                verbose_println!("Downloading #{}...", post.id);
+
+                if let Err(e) = save_post(&post, None).await {
+                    eprintln!("Error when saving #{}: {}", post.id, e);
                 }
             }
         }
@@ -271,3 +551,270 @@ pub async fn run(url: &str, matches: &ArgMatches<'_>) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+        thread::{self, JoinHandle},
+    };
+
+    // a request received by the stub server
+    struct StubRequest {
+        request_line: String,
+        headers: Vec<String>,
+        body: String,
+    }
+
+    impl StubRequest {
+        fn header(&self, name: &str) -> Option<&str> {
+            let prefix = format!("{}:", name.to_lowercase());
+
+            self.headers
+                .iter()
+                .find(|header| header.to_lowercase().starts_with(&prefix))
+                .map(|header| header[prefix.len()..].trim())
+        }
+    }
+
+    // serves one response per request on a local port, in order, returning the server's URL
+    // and the requests it received
+    fn stub_server(
+        responses: Vec<(&'static str, String)>,
+    ) -> (String, JoinHandle<Vec<StubRequest>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        let handle = thread::spawn(move || {
+            let mut requests = Vec::new();
+
+            for (extra_headers, response) in responses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+
+                let mut headers = Vec::new();
+
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+
+                    if line.trim().is_empty() {
+                        break;
+                    }
+
+                    headers.push(line.trim().to_string());
+                }
+
+                let mut request = StubRequest {
+                    request_line: request_line.trim().into(),
+                    headers,
+                    body: String::new(),
+                };
+
+                let length = request
+                    .header("content-length")
+                    .map_or(0, |length| length.parse().unwrap());
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                request.body = String::from_utf8_lossy(&body).into_owned();
+
+                write!(
+                    reader.get_mut(),
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n{}\r\n{}",
+                    response.len(),
+                    extra_headers,
+                    response
+                )
+                .unwrap();
+
+                requests.push(request);
+            }
+
+            requests
+        });
+
+        (url, handle)
+    }
+
+    // an image to search, in the temporary directory
+    fn query_file(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("get621-{}-{}.png", std::process::id(), name));
+        std::fs::write(&path, b"not really a png").unwrap();
+        path
+    }
+
+    fn candidate(id: u64, similarity: f64) -> Candidate {
+        Candidate {
+            id,
+            similarity,
+            file_url: None,
+            file_ext: None,
+            rating: None,
+        }
+    }
+
+    #[test]
+    fn sorts_candidates_most_similar_first() {
+        let mut candidates = vec![
+            candidate(1, 50.0),
+            candidate(2, 99.0),
+            candidate(3, 0.0),
+            candidate(4, 75.0),
+        ];
+
+        sort_candidates(&mut candidates);
+
+        let ids = candidates.iter().map(|c| c.id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![2, 4, 1, 3]);
+    }
+
+    #[test]
+    fn parses_iqdb_response() {
+        let json = serde_json::from_str(include_str!("../tests/fixtures/iqdb.json")).unwrap();
+        let candidates = iqdb_candidates(json).unwrap();
+
+        let ids = candidates.iter().map(|c| c.id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![1002, 1003, 1001]);
+
+        assert_eq!(candidates[0].similarity, 96.8);
+        assert_eq!(candidates[0].file_ext.as_deref(), Some("png"));
+        assert_eq!(candidates[0].rating.as_deref(), Some("s"));
+        assert!(candidates[1].file_url.is_none());
+    }
+
+    #[test]
+    fn parses_empty_iqdb_response() {
+        let json = serde_json::from_str(include_str!("../tests/fixtures/iqdb_empty.json")).unwrap();
+
+        assert!(iqdb_candidates(json).unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_unexpected_iqdb_response() {
+        let json = serde_json::json!([{ "post_id": 1, "post": {} }]);
+
+        assert!(matches!(
+            iqdb_candidates(json),
+            Err(Error::IqdbResponseParseError(_))
+        ));
+    }
+
+    #[test]
+    fn parses_saucenao_response() {
+        let response =
+            serde_json::from_str(include_str!("../tests/fixtures/saucenao.json")).unwrap();
+        let candidates = saucenao_candidates(response).unwrap();
+
+        // the result from another database is left out
+        let ids = candidates.iter().map(|c| c.id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![2001, 2002]);
+
+        assert_eq!(candidates[0].similarity, 93.75);
+        assert!(candidates[0].file_url.is_none());
+    }
+
+    #[test]
+    fn reports_saucenao_errors() {
+        let response =
+            serde_json::from_str(include_str!("../tests/fixtures/saucenao_error.json")).unwrap();
+
+        match saucenao_candidates(response) {
+            Err(Error::SauceNaoError(message)) => assert_eq!(message, "Search Rate Too High."),
+            _ => panic!("expected a SauceNAO error"),
+        }
+    }
+
+    #[tokio::test]
+    async fn queries_iqdb() {
+        let page = r#"<html><head><meta name="csrf-token" content="t0k3n"></head></html>"#;
+        let (url, server) = stub_server(vec![
+            ("Set-Cookie: _session=abc\r\n", page.into()),
+            ("", include_str!("../tests/fixtures/iqdb.json").into()),
+        ]);
+        let path = query_file("iqdb");
+
+        let candidates = Iqdb::new(&url).search(&path).await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let ids = candidates.iter().map(|c| c.id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![1002, 1003, 1001]);
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests[0].request_line, "GET /iqdb_queries HTTP/1.1");
+        assert_eq!(requests[1].request_line, "POST /iqdb_queries.json HTTP/1.1");
+        assert_eq!(requests[1].header("cookie"), Some("_session=abc"));
+        assert!(requests[1]
+            .header("content-type")
+            .unwrap()
+            .starts_with("multipart/form-data"));
+        assert!(requests[1]
+            .body
+            .contains("name=\"authenticity_token\"\r\n\r\nt0k3n\r\n"));
+        assert!(requests[1].body.contains("not really a png"));
+    }
+
+    #[tokio::test]
+    async fn fails_without_csrf_token() {
+        let (url, server) = stub_server(vec![(
+            "Set-Cookie: _session=abc\r\n",
+            "<html></html>".into(),
+        )]);
+        let path = query_file("iqdb-token");
+
+        let result = Iqdb::new(&url).search(&path).await;
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matches!(result, Err(Error::AuthTokenNotFound)));
+        server.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn queries_saucenao() {
+        let (url, server) = stub_server(vec![(
+            "",
+            include_str!("../tests/fixtures/saucenao.json").into(),
+        )]);
+        let path = query_file("saucenao");
+
+        let candidates = SauceNao::with_endpoint("k3y", &format!("{}/", url))
+            .search(&path)
+            .await
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let ids = candidates.iter().map(|c| c.id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![2001, 2002]);
+
+        let requests = server.join().unwrap();
+        assert_eq!(
+            requests[0].request_line,
+            "POST /search.php?output_type=2&db=29&api_key=k3y HTTP/1.1"
+        );
+        assert!(requests[0].body.contains("name=\"file\"; filename=\""));
+        assert!(requests[0].body.contains("not really a png"));
+    }
+
+    #[tokio::test]
+    async fn reports_saucenao_status() {
+        let (url, server) = stub_server(vec![(
+            "",
+            include_str!("../tests/fixtures/saucenao_error.json").into(),
+        )]);
+        let path = query_file("saucenao-error");
+
+        let result = SauceNao::with_endpoint("k3y", &url).search(&path).await;
+        std::fs::remove_file(&path).unwrap();
+
+        match result {
+            Err(Error::SauceNaoError(message)) => assert_eq!(message, "Search Rate Too High."),
+            _ => panic!("expected a SauceNAO error"),
+        }
+
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,232 @@
+use crate::{
+    common::{
+        get_json, get_json_list, output_mode_check, post_map_args, save_extras_args,
+        send_authenticated, user_agent, valid_parse, Credentials, Result, CLIENT,
+    },
+    dtext::{self, Format},
+    filter::filter_args,
+    pool::process_ordered_posts,
+};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use rs621::client::Client;
+use serde::Deserialize;
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    let id_arg = Arg::with_name("id")
+        .index(1)
+        .required(true)
+        .validator(|v| valid_parse::<u64>(&v, "Must be a positive integer."))
+        .help("The ID of the set");
+    let post_ids_arg = Arg::with_name("post_ids")
+        .index(2)
+        .required(true)
+        .multiple(true)
+        .validator(|v| valid_parse::<u64>(&v, "Must be a positive integer."))
+        .help("IDs of the posts");
+
+    SubCommand::with_name("set")
+        .about("Post set related commands")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            SubCommand::with_name("search")
+                .about("Search sets by name or owner")
+                .arg(
+                    Arg::with_name("name")
+                        .index(1)
+                        .help("Text contained in the name of the set"),
+                )
+                .arg(
+                    Arg::with_name("owner")
+                        .long("owner")
+                        .takes_value(true)
+                        .help("Name of the user who created the set"),
+                )
+                .arg(
+                    Arg::with_name("limit")
+                        .short("l")
+                        .long("limit")
+                        .takes_value(true)
+                        .default_value("20")
+                        .validator(|v| valid_parse::<u64>(&v, "Must be a positive integer."))
+                        .help("Maximum result count"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("show")
+                .about("Show information about a set")
+                .arg(id_arg.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("posts")
+                .about("Get the posts of a set, in order")
+                .args(&post_map_args())
+                .args(&filter_args())
+                .arg(
+                    Arg::with_name("save")
+                        .short("s")
+                        .long("save")
+                        .help("Download every result to ./<set_id>-i_<post_id>.<ext>"),
+                )
+                .args(&save_extras_args())
+                .arg(
+                    Arg::with_name("output_mode")
+                        .short("o")
+                        .long("output")
+                        .takes_value(true)
+                        .default_value("verbose")
+                        .validator(output_mode_check)
+                        .help("Set output mode; one of: id, raw, verbose, tree, dot"),
+                )
+                .arg(id_arg.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("add")
+                .about("Add posts to one of your sets")
+                .arg(id_arg.clone())
+                .arg(post_ids_arg.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("remove")
+                .about("Remove posts from one of your sets")
+                .arg(id_arg)
+                .arg(post_ids_arg),
+        )
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PostSet {
+    pub id: u64,
+    pub name: String,
+    pub shortname: String,
+    pub creator_id: u64,
+    #[serde(default)]
+    pub description: String,
+    pub post_count: u64,
+    pub is_public: bool,
+    #[serde(default)]
+    pub post_ids: Vec<u64>,
+}
+
+/// Searches sets by name and owner.
+pub async fn search(
+    url: &str,
+    name: Option<&str>,
+    owner: Option<&str>,
+    limit: u64,
+) -> Result<Vec<PostSet>> {
+    let name = name.map(|name| format!("*{}*", name));
+    let limit = limit.to_string();
+    let mut query = vec![("limit", limit.as_str())];
+
+    if let Some(ref name) = name {
+        query.push(("search[name]", name.as_str()));
+    }
+
+    if let Some(owner) = owner {
+        query.push(("search[creator_name]", owner));
+    }
+
+    get_json_list(url, "/post_sets.json", &query).await
+}
+
+/// Gets the set with the given ID.
+///
+/// The request is authenticated if credentials are given, so that private sets can be viewed
+/// by their owner.
+pub async fn get_set(url: &str, id: u64, credentials: Option<&Credentials>) -> Result<PostSet> {
+    let endpoint = format!("/post_sets/{}.json", id);
+
+    match credentials {
+        Some(credentials) => Ok(send_authenticated(
+            CLIENT.get(format!("{}{}", url, endpoint)),
+            credentials,
+        )
+        .await?
+        .json()
+        .await?),
+        None => get_json(url, &endpoint, &[]).await,
+    }
+}
+
+// get621 set add|remove ...
+async fn edit(url: &str, matches: &ArgMatches<'_>, action: &str) -> Result<()> {
+    let credentials = Credentials::from_matches(matches)?;
+    let id: u64 = matches.value_of("id").unwrap().parse().unwrap();
+    let form = matches
+        .values_of("post_ids")
+        .unwrap()
+        .map(|post_id| ("post_ids[]", post_id))
+        .collect::<Vec<_>>();
+
+    send_authenticated(
+        CLIENT
+            .post(format!("{}/post_sets/{}/{}.json", url, id, action))
+            .form(&form),
+        &credentials,
+    )
+    .await?;
+
+    let set = get_set(url, id, Some(&credentials)).await?;
+    println!("{} now has {} posts", set.name, set.post_count);
+
+    Ok(())
+}
+
+// get621 set ...
+pub async fn run(url: &str, matches: &ArgMatches<'_>) -> Result<()> {
+    match matches.subcommand() {
+        ("search", Some(sub_matches)) => {
+            let sets = search(
+                url,
+                sub_matches.value_of("name"),
+                sub_matches.value_of("owner"),
+                sub_matches.value_of("limit").unwrap().parse().unwrap(),
+            )
+            .await?;
+
+            for set in sets.iter() {
+                println!("#{} {} ({} posts)", set.id, set.name, set.post_count);
+            }
+
+            if sets.is_empty() {
+                println!("No set found.");
+            }
+
+            Ok(())
+        }
+        ("show", Some(sub_matches)) => {
+            let set = get_set(
+                url,
+                sub_matches.value_of("id").unwrap().parse().unwrap(),
+                Credentials::from_matches(sub_matches).ok().as_ref(),
+            )
+            .await?;
+
+            println!("#{} {} ({})", set.id, set.name, set.shortname);
+            println!("Owner: user #{}", set.creator_id);
+            println!("Posts: {}", set.post_count);
+            println!("Public: {}", if set.is_public { "yes" } else { "no" });
+
+            if !set.description.is_empty() {
+                println!();
+                print!("{}", dtext::render(&set.description, Format::terminal()));
+            }
+
+            Ok(())
+        }
+        ("posts", Some(sub_matches)) => {
+            let set = get_set(
+                url,
+                sub_matches.value_of("id").unwrap().parse().unwrap(),
+                Credentials::from_matches(sub_matches).ok().as_ref(),
+            )
+            .await?;
+            let client = Client::new(url, user_agent())?;
+
+            process_ordered_posts(url, &client, set.id, &set.post_ids, sub_matches).await
+        }
+        ("add", Some(sub_matches)) => edit(url, sub_matches, "add_posts").await,
+        ("remove", Some(sub_matches)) => edit(url, sub_matches, "remove_posts").await,
+        _ => unreachable!(),
+    }
+}
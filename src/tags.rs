@@ -0,0 +1,294 @@
+use crate::common::{get_json_list, get_json_pages, valid_parse, OutputMode, Result};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    let output_mode_arg = || {
+        Arg::with_name("output_mode")
+            .short("o")
+            .long("output")
+            .takes_value(true)
+            .default_value("verbose")
+            .possible_values(&["id", "raw", "verbose"])
+            .help("Set output mode; one of: id, raw, verbose")
+    };
+    let relationship_args = || {
+        vec![
+            Arg::with_name("tag")
+                .index(1)
+                .required(true)
+                .help("The tag to look up"),
+            output_mode_arg(),
+        ]
+    };
+
+    SubCommand::with_name("tags")
+        .about("Tag related commands")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            SubCommand::with_name("search")
+                .about("Search tags by name")
+                .arg(
+                    Arg::with_name("pattern")
+                        .index(1)
+                        .required(true)
+                        .help("Tag name to search; can contain * wildcards"),
+                )
+                .arg(
+                    Arg::with_name("order")
+                        .long("order")
+                        .takes_value(true)
+                        .default_value("count")
+                        .possible_values(&["count", "name", "date"])
+                        .help("Set the order of the results"),
+                )
+                .arg(
+                    Arg::with_name("limit")
+                        .short("l")
+                        .long("limit")
+                        .takes_value(true)
+                        .default_value("10")
+                        .validator(|v| valid_parse::<u64>(&v, "Must be a positive integer."))
+                        .help("Maximum result count"),
+                )
+                .arg(
+                    Arg::with_name("related")
+                        .short("r")
+                        .long("related")
+                        .help("Show the tags related to each result"),
+                )
+                .arg(output_mode_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("alias")
+                .about("Show the aliases of a tag")
+                .args(&relationship_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("implications")
+                .about("Show the implications of a tag")
+                .args(&relationship_args()),
+        )
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Tag {
+    pub id: u64,
+    pub name: String,
+    pub post_count: u64,
+    pub category: u8,
+    #[serde(default)]
+    pub related_tags: Option<String>,
+    pub created_at: Option<String>,
+}
+
+impl Tag {
+    pub fn category_name(&self) -> &'static str {
+        match self.category {
+            0 => "general",
+            1 => "artist",
+            3 => "copyright",
+            4 => "character",
+            5 => "species",
+            6 => "invalid",
+            7 => "meta",
+            8 => "lore",
+            _ => "unknown",
+        }
+    }
+
+    /// Names of the related tags, most related first.
+    pub fn related(&self) -> Vec<&str> {
+        // "tag_a weight_a tag_b weight_b ..."
+        self.related_tags
+            .as_deref()
+            .unwrap_or("")
+            .split_whitespace()
+            .step_by(2)
+            .filter(|name| *name != self.name)
+            .collect()
+    }
+}
+
+/// An alias or an implication between two tags.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TagRelationship {
+    pub id: u64,
+    pub antecedent_name: String,
+    pub consequent_name: String,
+    pub status: String,
+    pub reason: Option<String>,
+    pub created_at: Option<String>,
+}
+
+/// Gets the aliases (`/tag_aliases.json`) or implications (`/tag_implications.json`) involving a
+/// tag, on either side.
+pub async fn relationships(url: &str, endpoint: &str, tag: &str) -> Result<Vec<TagRelationship>> {
+    get_json_list(url, endpoint, &[("search[name_matches]", tag)]).await
+}
+
+/// Gets the tag that `tag` is an alias of, if any.
+pub async fn resolve_alias(url: &str, tag: &str) -> Result<Option<String>> {
+    let aliases: Vec<TagRelationship> = get_json_list(
+        url,
+        "/tag_aliases.json",
+        &[
+            ("search[antecedent_name]", tag),
+            ("search[status]", "active"),
+        ],
+    )
+    .await?;
+
+    Ok(aliases
+        .into_iter()
+        .next()
+        .map(|alias| alias.consequent_name))
+}
+
+/// Rewrites search tags through their aliases, printing every rewritten tag.
+///
+/// Each value can hold several tags separated by spaces. Metatags, wildcards and groups are left
+/// untouched.
+pub async fn resolve_aliases(url: &str, tags: &[&str]) -> Result<Vec<String>> {
+    let mut resolved = Vec::with_capacity(tags.len());
+
+    for tag in tags.iter().flat_map(|tag| tag.split_whitespace()) {
+        let name = tag.trim_start_matches(['-', '~']);
+        let prefix = &tag[..tag.len() - name.len()];
+
+        if name.is_empty() || name.contains([':', '*', '(', ')']) {
+            resolved.push(tag.to_string());
+            continue;
+        }
+
+        match resolve_alias(url, name).await? {
+            Some(consequent) => {
+                eprintln!("{} is an alias of {}", name, consequent);
+                resolved.push(format!("{}{}", prefix, consequent));
+            }
+            None => resolved.push(tag.to_string()),
+        }
+    }
+
+    Ok(resolved)
+}
+
+/// Searches tags whose name matches `pattern`.
+pub async fn search(url: &str, pattern: &str, order: &str, limit: u64) -> Result<Vec<Tag>> {
+    get_json_list(
+        url,
+        "/tags.json",
+        &[
+            ("search[name_matches]", pattern),
+            ("search[order]", order),
+            ("limit", &limit.to_string()),
+        ],
+    )
+    .await
+}
+
+/// Gets the post count of each of the given tags, leaving out the ones that don't exist.
+pub async fn post_counts(url: &str, names: &[String]) -> Result<HashMap<String, u64>> {
+    let tags: Vec<Tag> =
+        get_json_pages(url, "/tags.json", &[("search[name]", &names.join(","))]).await?;
+
+    Ok(tags
+        .into_iter()
+        .map(|tag| (tag.name, tag.post_count))
+        .collect())
+}
+
+// get621 tags alias|implications ...
+async fn show_relationships(url: &str, endpoint: &str, matches: &ArgMatches<'_>) -> Result<()> {
+    let tag = matches.value_of("tag").unwrap();
+    let relationships = relationships(url, endpoint, tag).await?;
+
+    match matches.value_of("output_mode").unwrap().into() {
+        OutputMode::Id => {
+            for relationship in relationships.iter() {
+                println!("{}", relationship.id);
+            }
+        }
+        OutputMode::Raw => {
+            for relationship in relationships.iter() {
+                println!("{}", serde_json::to_string(relationship)?);
+            }
+        }
+        _ => {
+            for relationship in relationships.iter() {
+                println!(
+                    "{} -> {} ({})",
+                    relationship.antecedent_name, relationship.consequent_name, relationship.status
+                );
+            }
+
+            if relationships.is_empty() {
+                println!("Nothing found.");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// get621 tags search ...
+async fn search_tags(url: &str, matches: &ArgMatches<'_>) -> Result<()> {
+    let pattern = matches.value_of("pattern").unwrap();
+    let order = matches.value_of("order").unwrap();
+    let limit = matches.value_of("limit").unwrap().parse().unwrap();
+    let flag_related = matches.is_present("related");
+
+    let tags = search(url, pattern, order, limit).await?;
+
+    match matches.value_of("output_mode").unwrap().into() {
+        OutputMode::Id => {
+            for tag in tags.iter() {
+                println!("{}", tag.id);
+            }
+        }
+        OutputMode::Raw => {
+            for tag in tags.iter() {
+                println!("{}", serde_json::to_string(tag)?);
+            }
+        }
+        _ => {
+            for (i, tag) in tags.iter().enumerate() {
+                if i > 0 {
+                    println!("----------------");
+                }
+
+                println!("{} ({})", tag.name, tag.category_name());
+                println!("Posts: {}", tag.post_count);
+
+                if let Some(ref created_at) = tag.created_at {
+                    println!("Created at: {}", created_at);
+                }
+
+                if flag_related {
+                    println!("Related tags:\n    {}", tag.related().join(" "));
+                }
+            }
+
+            if tags.is_empty() {
+                println!("No tag found.");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// get621 tags ...
+pub async fn run(url: &str, matches: &ArgMatches<'_>) -> Result<()> {
+    match matches.subcommand() {
+        ("search", Some(sub_matches)) => search_tags(url, sub_matches).await,
+        ("alias", Some(sub_matches)) => {
+            show_relationships(url, "/tag_aliases.json", sub_matches).await
+        }
+        ("implications", Some(sub_matches)) => {
+            show_relationships(url, "/tag_implications.json", sub_matches).await
+        }
+        _ => unreachable!(),
+    }
+}
@@ -0,0 +1,264 @@
+use crate::{
+    common::{
+        file_part, parse_rating_level, user_agent, valid_parse, Credentials, Error, Result, CLIENT,
+    },
+    identify,
+};
+use clap::{App, Arg, ArgMatches, SubCommand};
+use reqwest::multipart;
+use rs621::client::Client;
+use serde::Deserialize;
+use std::{
+    fs::{self, File},
+    io::{self, BufReader},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+// file types accepted by e621
+const ALLOWED_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "gif", "webm"];
+const MAX_FILE_SIZE: u64 = 100 * 1024 * 1024;
+const MAX_SOURCES: usize = 10;
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("upload")
+        .about("Upload posts")
+        .arg(
+            Arg::with_name("file")
+                .index(1)
+                .required_unless("manifest")
+                .help("The file to upload"),
+        )
+        .arg(
+            Arg::with_name("tags")
+                .short("t")
+                .long("tags")
+                .takes_value(true)
+                .required_unless("manifest")
+                .help("Tags of the post, separated by spaces"),
+        )
+        .arg(
+            Arg::with_name("rating")
+                .short("r")
+                .long("rating")
+                .takes_value(true)
+                .required_unless("manifest")
+                .possible_values(&["s", "q", "e", "safe", "questionable", "explicit"])
+                .help("Rating of the post"),
+        )
+        .arg(
+            Arg::with_name("source")
+                .long("source")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Source URL of the post; can be given several times"),
+        )
+        .arg(
+            Arg::with_name("parent")
+                .long("parent")
+                .takes_value(true)
+                .validator(|v| valid_parse::<u64>(&v, "Must be a positive integer."))
+                .help("ID of the parent post"),
+        )
+        .arg(
+            Arg::with_name("description")
+                .long("description")
+                .takes_value(true)
+                .help("Description of the post, in DText"),
+        )
+        .arg(
+            Arg::with_name("manifest")
+                .short("m")
+                .long("manifest")
+                .takes_value(true)
+                .conflicts_with("file")
+                .help("Upload every file listed in a JSON manifest"),
+        )
+}
+
+/// A file to upload along with the information of its post.
+///
+/// A manifest is a JSON list of uploads, whose file paths are relative to the manifest.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Upload {
+    pub file: PathBuf,
+    pub tags: String,
+    pub rating: String,
+    #[serde(default)]
+    pub sources: Vec<String>,
+    pub parent: Option<u64>,
+    #[serde(default)]
+    pub description: String,
+}
+
+impl From<&ArgMatches<'_>> for Upload {
+    fn from(matches: &ArgMatches<'_>) -> Self {
+        Upload {
+            file: PathBuf::from(matches.value_of("file").unwrap()),
+            tags: matches.value_of("tags").unwrap().into(),
+            rating: matches.value_of("rating").unwrap().into(),
+            sources: matches
+                .values_of("source")
+                .map_or_else(Vec::new, |v| v.map(String::from).collect()),
+            parent: matches.value_of("parent").map(|v| v.parse().unwrap()),
+            description: matches.value_of("description").unwrap_or("").into(),
+        }
+    }
+}
+
+impl Upload {
+    /// Checks the upload before sending it, returning its rating as expected by the API.
+    pub fn validate(&self) -> Result<&'static str> {
+        let invalid = |reason: String| {
+            Err(Error::InvalidUpload(format!(
+                "{}: {}",
+                self.file.display(),
+                reason
+            )))
+        };
+
+        let extension = self
+            .file
+            .extension()
+            .map_or_else(String::new, |ext| ext.to_string_lossy().to_lowercase());
+
+        if !ALLOWED_EXTENSIONS.contains(&extension.as_str()) {
+            return invalid(format!("unsupported file type \"{}\"", extension));
+        }
+
+        if fs::metadata(&self.file)?.len() > MAX_FILE_SIZE {
+            return invalid(format!("larger than {} MB", MAX_FILE_SIZE / 1024 / 1024));
+        }
+
+        if self.tags.split_whitespace().next().is_none() {
+            return invalid(String::from("no tags"));
+        }
+
+        for tag in self.tags.split_whitespace() {
+            if tag.starts_with(['-', '~']) || tag.contains(['*', ',']) {
+                return invalid(format!("invalid tag \"{}\"", tag));
+            }
+        }
+
+        if self.sources.len() > MAX_SOURCES {
+            return invalid(format!("more than {} sources", MAX_SOURCES));
+        }
+
+        match parse_rating_level(&self.rating) {
+            Some(0) => Ok("s"),
+            Some(1) => Ok("q"),
+            Some(2) => Ok("e"),
+            _ => invalid(format!("invalid rating \"{}\"", self.rating)),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct UploadResponse {
+    success: bool,
+    post_id: Option<u64>,
+    reason: Option<String>,
+    message: Option<String>,
+}
+
+/// Uploads a file, returning the ID of the new post.
+///
+/// `rating` is the one returned by [`Upload::validate`].
+pub async fn upload(
+    url: &str,
+    credentials: &Credentials,
+    upload: &Upload,
+    rating: &str,
+) -> Result<u64> {
+    let mut form = multipart::Form::new()
+        .part("upload[file]", file_part(&upload.file)?)
+        .text("upload[tag_string]", upload.tags.clone())
+        .text("upload[rating]", rating.to_string())
+        .text("upload[source]", upload.sources.join("\n"))
+        .text("upload[description]", upload.description.clone());
+
+    if let Some(parent) = upload.parent {
+        form = form.text("upload[parent_id]", parent.to_string());
+    }
+
+    let res = credentials
+        .authenticate(CLIENT.post(format!("{}/uploads.json", url)).multipart(form))
+        .send()
+        .await?;
+    let status = res.status();
+
+    match res.json::<UploadResponse>().await {
+        Ok(UploadResponse {
+            success: true,
+            post_id: Some(post_id),
+            ..
+        }) => Ok(post_id),
+        Ok(response) => Err(Error::UploadFailed(
+            response
+                .message
+                .or(response.reason)
+                .unwrap_or_else(|| status.to_string()),
+        )),
+        Err(_) => Err(Error::Http(status.as_u16())),
+    }
+}
+
+// reads the uploads listed in a manifest
+fn read_manifest(path: &Path) -> Result<Vec<Upload>> {
+    let mut uploads: Vec<Upload> = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+    for upload in uploads.iter_mut() {
+        upload.file = dir.join(&upload.file);
+    }
+
+    Ok(uploads)
+}
+
+// get621 upload ...
+pub async fn run(url: &str, matches: &ArgMatches<'_>) -> Result<()> {
+    let credentials = Credentials::from_matches(matches)?;
+
+    let uploads = match matches.value_of("manifest") {
+        Some(manifest) => read_manifest(Path::new(manifest))?,
+        None => vec![Upload::from(matches)],
+    };
+
+    // nothing is uploaded unless every file is valid
+    let ratings = uploads
+        .iter()
+        .map(Upload::validate)
+        .collect::<Result<Vec<_>>>()?;
+
+    let hashes = uploads
+        .iter()
+        .map(|upload| fs::read(&upload.file).map(|bytes| format!("{:x}", md5::compute(&bytes))))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let client = Client::new(url, user_agent())?;
+    let existing = identify::lookup(&client, &hashes).await?;
+
+    let mut first = true;
+
+    for ((upload, rating), md5) in uploads.iter().zip(ratings).zip(hashes.iter()) {
+        if let Some(post) = existing.get(md5) {
+            println!("{}: already posted as #{}", upload.file.display(), post.id);
+            continue;
+        }
+
+        // stay well below the API rate limit
+        if !first {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+
+        first = false;
+
+        match self::upload(url, &credentials, upload, rating).await {
+            Ok(post_id) => println!("{}: uploaded as #{}", upload.file.display(), post_id),
+            Err(e) => eprintln!("{}: {}", upload.file.display(), e),
+        }
+    }
+
+    Ok(())
+}
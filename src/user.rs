@@ -0,0 +1,97 @@
+use crate::{
+    common::{get_json, pipeline_args, user_agent, valid_parse, Error, Result},
+    normal,
+};
+use clap::{App, Arg, ArgMatches, SubCommand};
+use rs621::client::Client;
+use serde::Deserialize;
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("user")
+        .about("Show the statistics of a user")
+        .arg(
+            Arg::with_name("user")
+                .index(1)
+                .required(true)
+                .help("Name or ID of the user"),
+        )
+        .arg(
+            Arg::with_name("uploads")
+                .long("uploads")
+                .conflicts_with("favorites")
+                .help("Search the posts uploaded by the user instead"),
+        )
+        .arg(
+            Arg::with_name("favorites")
+                .long("favorites")
+                .help("Search the favorites of the user instead"),
+        )
+        .arg(
+            Arg::with_name("limit")
+                .short("l")
+                .long("limit")
+                .takes_value(true)
+                .default_value("1")
+                .validator(|v| valid_parse::<u64>(&v, "Must be a positive integer."))
+                .help("Maximum search result count with --uploads or --favorites"),
+        )
+        .args(&pipeline_args())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct User {
+    pub id: u64,
+    pub name: String,
+    pub level_string: String,
+    pub created_at: String,
+    pub post_upload_count: u64,
+    pub favorite_count: Option<u64>,
+    pub comment_count: Option<u64>,
+    pub is_banned: bool,
+}
+
+/// Gets a user by name or ID.
+pub async fn get_user(url: &str, user: &str) -> Result<User> {
+    match get_json(url, &format!("/users/{}.json", user), &[]).await {
+        Err(Error::Http(404)) => Err(Error::UserNotFound(user.into())),
+        res => res,
+    }
+}
+
+// get621 user ...
+pub async fn run(url: &str, matches: &ArgMatches<'_>) -> Result<()> {
+    let user = get_user(url, matches.value_of("user").unwrap()).await?;
+
+    let search = if matches.is_present("uploads") {
+        Some(format!("user:{}", user.name))
+    } else if matches.is_present("favorites") {
+        Some(format!("fav:{}", user.name))
+    } else {
+        None
+    };
+
+    if let Some(search) = search {
+        let client = Client::new(url, user_agent())?;
+        return normal::search(url, &client, vec![search], matches).await;
+    }
+
+    if user.is_banned {
+        println!("{} (#{}, banned)", user.name, user.id);
+    } else {
+        println!("{} (#{})", user.name, user.id);
+    }
+
+    println!("Level: {}", user.level_string);
+    println!("Joined: {}", user.created_at);
+    println!("Uploads: {}", user.post_upload_count);
+
+    if let Some(count) = user.favorite_count {
+        println!("Favorites: {}", count);
+    }
+
+    if let Some(count) = user.comment_count {
+        println!("Comments: {}", count);
+    }
+
+    Ok(())
+}
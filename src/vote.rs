@@ -0,0 +1,123 @@
+use crate::common::{get_json, send_authenticated, Credentials, Result, CLIENT};
+use clap::{App, Arg, ArgMatches, SubCommand};
+use serde::Deserialize;
+use std::io::{self, BufRead};
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("vote")
+        .about("Vote on posts")
+        .arg(
+            Arg::with_name("id")
+                .index(1)
+                .required(true)
+                .validator(|v| match v.as_str() {
+                    "-" => Ok(()),
+                    v => v
+                        .parse::<u64>()
+                        .map(|_| ())
+                        .map_err(|_| String::from("Must be a positive integer or \"-\".")),
+                })
+                .help("ID of the post; \"-\" reads IDs from the standard input, one per line"),
+        )
+        .arg(
+            Arg::with_name("vote")
+                .index(2)
+                .required(true)
+                .possible_values(&["up", "down", "clear"]),
+        )
+}
+
+/// Score of a post after a vote.
+#[derive(Debug, Deserialize)]
+pub struct Score {
+    pub up: i64,
+    pub down: i64,
+    #[serde(alias = "score")]
+    pub total: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct PostScore {
+    score: Score,
+}
+
+#[derive(Debug, Deserialize)]
+struct PostResponse {
+    post: PostScore,
+}
+
+/// Votes a post up (`1`) or down (`-1`), returning its new score.
+pub async fn vote(url: &str, credentials: &Credentials, post_id: u64, vote: i8) -> Result<Score> {
+    let res = send_authenticated(
+        CLIENT
+            .post(format!("{}/posts/{}/votes.json", url, post_id))
+            .form(&[("score", vote.to_string().as_str()), ("no_unvote", "true")]),
+        credentials,
+    )
+    .await?;
+
+    Ok(res.json().await?)
+}
+
+/// Removes the vote of the authenticated user on a post, returning its new score.
+pub async fn clear(url: &str, credentials: &Credentials, post_id: u64) -> Result<Score> {
+    send_authenticated(
+        CLIENT.delete(format!("{}/posts/{}/votes.json", url, post_id)),
+        credentials,
+    )
+    .await?;
+
+    let res: PostResponse = get_json(url, &format!("/posts/{}.json", post_id), &[]).await?;
+
+    Ok(res.post.score)
+}
+
+// get621 vote ...
+pub async fn run(url: &str, matches: &ArgMatches<'_>) -> Result<()> {
+    let credentials = Credentials::from_matches(matches)?;
+    let direction = matches.value_of("vote").unwrap();
+
+    let ids = match matches.value_of("id").unwrap() {
+        "-" => {
+            let mut ids = Vec::new();
+
+            for line in io::stdin().lock().lines() {
+                let line = line?;
+                let line = line.trim();
+
+                if line.is_empty() {
+                    continue;
+                }
+
+                match line.parse() {
+                    Ok(id) => ids.push(id),
+                    Err(_) => eprintln!("Skipping invalid post ID: {}", line),
+                }
+            }
+
+            ids
+        }
+        id => vec![id.parse().unwrap()],
+    };
+
+    for id in ids {
+        let score = match direction {
+            "up" => vote(url, &credentials, id, 1).await,
+            "down" => vote(url, &credentials, id, -1).await,
+            _ => clear(url, &credentials, id).await,
+        };
+
+        match score {
+            Ok(score) => println!(
+                "#{}: {} (+{} / -{})",
+                id,
+                score.total,
+                score.up,
+                score.down.abs()
+            ),
+            Err(e) => eprintln!("#{}: {}", id, e),
+        }
+    }
+
+    Ok(())
+}
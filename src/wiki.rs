@@ -0,0 +1,129 @@
+use crate::{
+    common::{get_json_list, valid_parse, Error, Result},
+    dtext::{self, Format},
+};
+use clap::{App, Arg, ArgMatches, SubCommand};
+use serde::Deserialize;
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("wiki")
+        .about("Read wiki pages")
+        .arg(
+            Arg::with_name("title")
+                .index(1)
+                .required(true)
+                .multiple(true)
+                .help("Title of the wiki page (e.g. a tag name)"),
+        )
+        .arg(
+            Arg::with_name("raw")
+                .long("raw")
+                .help("Print the DText markup of the page as is"),
+        )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .takes_value(true)
+                .possible_values(&["ansi", "plain", "html", "markdown"])
+                .help(
+                    "Format to render the page in [default: ansi on a terminal, plain otherwise]",
+                ),
+        )
+        .arg(
+            Arg::with_name("search")
+                .long("search")
+                .help("List the pages whose title contains the given text"),
+        )
+        .arg(
+            Arg::with_name("limit")
+                .short("l")
+                .long("limit")
+                .takes_value(true)
+                .default_value("20")
+                .validator(|v| valid_parse::<u64>(&v, "Must be a positive integer."))
+                .help("Maximum result count when searching"),
+        )
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WikiPage {
+    pub title: String,
+    pub body: String,
+    #[serde(default)]
+    pub other_names: Vec<String>,
+    pub updated_at: Option<String>,
+}
+
+/// Gets the wiki page with the given title.
+pub async fn get_page(url: &str, title: &str) -> Result<Option<WikiPage>> {
+    let pages: Vec<WikiPage> =
+        get_json_list(url, "/wiki_pages.json", &[("search[title]", title)]).await?;
+
+    Ok(pages.into_iter().next())
+}
+
+/// Searches the wiki pages whose title contains `fragment`.
+pub async fn search(url: &str, fragment: &str, limit: u64) -> Result<Vec<WikiPage>> {
+    get_json_list(
+        url,
+        "/wiki_pages.json",
+        &[
+            ("search[title]", &format!("*{}*", fragment)),
+            ("limit", &limit.to_string()),
+        ],
+    )
+    .await
+}
+
+// get621 wiki ...
+pub async fn run(url: &str, matches: &ArgMatches<'_>) -> Result<()> {
+    // wiki titles are written like tags
+    let title = matches
+        .values_of("title")
+        .unwrap()
+        .collect::<Vec<_>>()
+        .join("_")
+        .to_lowercase();
+    let limit = matches.value_of("limit").unwrap().parse().unwrap();
+
+    if matches.is_present("search") {
+        let pages = search(url, &title, limit).await?;
+
+        for page in pages.iter() {
+            println!("{}", page.title);
+        }
+
+        if pages.is_empty() {
+            println!("No wiki page found.");
+        }
+
+        return Ok(());
+    }
+
+    let page = get_page(url, &title)
+        .await?
+        .ok_or(Error::WikiPageNotFound(title))?;
+
+    if matches.is_present("raw") {
+        println!("{}", page.body);
+    } else {
+        let format = matches
+            .value_of("format")
+            .map_or_else(Format::terminal, |f| f.parse().unwrap());
+
+        print!("{}", dtext::render(&format!("h1. {}", page.title), format));
+
+        if !page.other_names.is_empty() {
+            println!("Other names: {}", page.other_names.join(", "));
+        }
+
+        if let Some(ref updated_at) = page.updated_at {
+            println!("Updated at: {}", updated_at);
+        }
+
+        println!();
+        print!("{}", dtext::render(&page.body, format));
+    }
+
+    Ok(())
+}